@@ -1,20 +1,45 @@
-use crate::dictionary::{Dictionary, DictionaryConfig};
-use crate::files::{FileError, WriteMode, extension, file_size, read_dir, write_string};
+use crate::checkpoint::Checkpoint;
+use crate::dictionary::{Dictionary, DictionaryConfig, HEADER, Model};
+use crate::files::{FileError, WriteMode, exists, extension, file_size, for_each_chunk, merge_files, read_dir, read_string, remove_file, rename, write_string};
 use crate::log::{initialize_log_file, write_log};
 use crate::multi::{MessageFromMain, MessageToMain, init_channels};
+use crate::normalizer::normalize;
+use crate::unigram::construct_unigram_dictionary;
+use crate::wordpiece::construct_wordpiece_dictionary;
 use crate::utils::prettify_file_size;
 use smallvec::{SmallVec, smallvec};
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::TryRecvError;
 use std::thread::sleep;
 use std::time::Duration;
 
+mod allocator;
+mod mapped;
+mod parallel;
+mod scoring;
+mod trainer;
+mod units;
+
 #[cfg(test)]
 mod tests;
 
+pub use allocator::UnitAllocator;
+pub use mapped::MappedUnits;
+pub use parallel::{count_pairs_parallel, replace_pair_in_place_parallel, threads_for};
+
+#[cfg(test)]
+pub use parallel::assign_pair_to_new_unit_parallel;
+pub use scoring::{MergeCandidate, MergeScorer, Scoring};
+pub use trainer::{Merge, Observer, StopReason, Trainer};
+pub use units::{UnitLike, Units};
+
 /// It stops iteration if the string gets too small
 pub const MINIMUN_STRING_LENGTH: usize = 16;
 
+/// When training from files, they're read by blocks of this size.
+pub const STREAMING_BLOCK_SIZE: usize = 1 << 20;
+
 // 0 ~ 255: byte
 // 256 ~ : token
 pub type Unit = u32;
@@ -46,10 +71,106 @@ pub fn from_pair(p: Pair) -> (Unit, Unit) {
     ((p >> 32) as Unit, (p & 0xffff_ffff) as Unit)
 }
 
+#[cfg(test)]
 pub fn bytes_to_units(bytes: &[u8]) -> Vec<Unit> {
     bytes.iter().map(|byte| *byte as Unit).collect()
 }
 
+/// Characters that cover `coverage` of `bytes` get their own units, which are added to `unit_map`.\
+/// Rare characters and invalid UTF-8 sequences fall back to byte units.
+pub fn chars_to_units(
+    bytes: &[u8],
+    coverage: f64,
+    unit_map: &mut UnitMapInternal,
+    allocator: &mut UnitAllocator,
+) -> Vec<Unit> {
+    let mut char_counts = HashMap::new();
+    let total = count_chars(bytes, &mut char_counts);
+    let char_units = assign_char_units(char_counts, total, coverage, unit_map, allocator);
+
+    let mut result = Vec::with_capacity(bytes.len());
+    push_char_units(bytes, &char_units, |unit| { result.push(unit); });
+
+    result
+}
+
+// it returns the number of characters
+fn count_chars(bytes: &[u8], char_counts: &mut HashMap<char, usize>) -> usize {
+    let mut total = 0;
+
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match char_counts.get_mut(&c) {
+                Some(n) => {
+                    *n += 1;
+                },
+                None => {
+                    char_counts.insert(c, 1);
+                },
+            }
+
+            total += 1;
+        }
+    }
+
+    total
+}
+
+// characters that cover `coverage` of `total`
+fn assign_char_units(
+    char_counts: HashMap<char, usize>,
+    total: usize,
+    coverage: f64,
+    unit_map: &mut UnitMapInternal,
+    allocator: &mut UnitAllocator,
+) -> HashMap<char, Unit> {
+    let mut char_counts = char_counts.into_iter().collect::<Vec<_>>();
+    char_counts.sort_by_key(|(c, count)| (usize::MAX - *count, *c));
+
+    let mut char_units = HashMap::new();
+    let mut covered = 0;
+
+    for (c, count) in char_counts.into_iter() {
+        if covered as f64 >= coverage * total as f64 {
+            break;
+        }
+
+        covered += count;
+
+        // ascii characters are already single-byte units
+        if !c.is_ascii() {
+            let new_unit = allocator.allocate();
+            unit_map.insert(new_unit, c.to_string().as_bytes().into());
+            char_units.insert(c, new_unit);
+        }
+    }
+
+    char_units
+}
+
+fn push_char_units<F: FnMut(Unit)>(bytes: &[u8], char_units: &HashMap<char, Unit>, mut push: F) {
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match char_units.get(&c) {
+                Some(unit) => {
+                    push(*unit);
+                },
+                None => {
+                    let mut buffer = [0; 4];
+
+                    for byte in c.encode_utf8(&mut buffer).as_bytes() {
+                        push(*byte as Unit);
+                    }
+                },
+            }
+        }
+
+        for byte in chunk.invalid().iter() {
+            push(*byte as Unit);
+        }
+    }
+}
+
 // for now, it's only used for tests
 #[cfg(test)]
 pub fn units_to_bytes(
@@ -94,10 +215,10 @@ pub fn construct_dictionary_from_dir(config: DictionaryConfig) -> Result<Diction
     let ext_to_see = Some(config.dir_option.ext.clone());
 
     let mut files_with_sizes = files.iter().filter(
-        |file| match (extension(file), file_size(file)) {
-            (Ok(ext), Ok(_size)) if ext == ext_to_see => true,
-            _ => false,
-        }
+        |file| matches!(
+            (extension(file), file_size(file)),
+            (Ok(ext), Ok(_size)) if ext == ext_to_see
+        )
     ).map(
         |file| (file.to_string(), file_size(file).unwrap())
     ).collect::<Vec<_>>();
@@ -114,6 +235,31 @@ pub fn construct_dictionary_from_dir(config: DictionaryConfig) -> Result<Diction
         ),
     );
 
+    let mut state = MasterCheckpoint::new(&config);
+
+    if let Some(path) = &config.checkpoint_at {
+        if config.resume_from_checkpoint && exists(path) {
+            match MasterCheckpoint::load(path) {
+                Ok(checkpoint) => {
+                    state = checkpoint;
+
+                    write_log(
+                        config.write_log_at.clone(),
+                        "master",
+                        &format!("resumed from checkpoint {path}: {} chunks are already done", state.finished_chunks.len()),
+                    );
+                },
+                Err(e) => {
+                    write_log(
+                        config.write_log_at.clone(),
+                        "master",
+                        &format!("failed to load checkpoint {path}, so it starts over: {e}"),
+                    );
+                },
+            }
+        }
+    }
+
     let mut worker_index = 0;
     let mut done = 0;
     let mut file_index = 0;
@@ -129,19 +275,29 @@ pub fn construct_dictionary_from_dir(config: DictionaryConfig) -> Result<Diction
             file_index += 1;
         }
 
-        write_log(
-            config.write_log_at.clone(),
-            "master",
-            &format!(
-                "gave jobs to a worker: {} files (total size {})",
-                files_to_read.len(),
-                prettify_file_size(curr_chunk_size as u64),
-            ),
-        );
+        if state.finished_chunks.contains(&worker_index) {
+            write_log(
+                config.write_log_at.clone(),
+                "master",
+                &format!("skipped chunk {worker_index}: it's in the checkpoint"),
+            );
+        }
 
-        channels[worker_index % channels.len()].send(
-            MessageFromMain::ReadTheseFiles(files_to_read)
-        ).unwrap();
+        else {
+            write_log(
+                config.write_log_at.clone(),
+                "master",
+                &format!(
+                    "gave jobs to a worker: {} files (total size {})",
+                    files_to_read.len(),
+                    prettify_file_size(curr_chunk_size as u64),
+                ),
+            );
+
+            channels[worker_index % channels.len()].send(
+                MessageFromMain::ReadTheseFiles(worker_index, files_to_read)
+            ).unwrap();
+        }
 
         if file_index == files_with_sizes.len() {
             break;
@@ -150,17 +306,72 @@ pub fn construct_dictionary_from_dir(config: DictionaryConfig) -> Result<Diction
         worker_index += 1;
     }
 
-    let mut result = Dictionary::empty();
-
     loop {
         let mut has_update = false;
 
         for channel in channels.iter() {
             match channel.try_recv() {
                 Ok(msg) => match msg {
-                    MessageToMain::NewDictionary(dictionary) => {
+                    // snapshots of a chunk are merged with the chunk, so that a checkpoint has both or neither
+                    MessageToMain::NewDictionary(chunk_index, dictionary, snapshots) => {
                         has_update = true;
-                        result.merge(&dictionary);
+                        state.merge_chunk(chunk_index, &dictionary, &snapshots, &config);
+
+                        // snapshot files are the results, not checkpoints
+                        // Like checkpoints, a failed write is logged and the training goes on.
+                        if let Some(path) = &config.snapshot_at {
+                            for (size, _) in snapshots.iter() {
+                                if let Err(e) = state.snapshots[size].save(&snapshot_path(path, *size)) {
+                                    write_log(
+                                        config.write_log_at.clone(),
+                                        "master",
+                                        &format!("failed to save snapshot of size {size}: {e}"),
+                                    );
+                                }
+                            }
+                        }
+
+                        if let Some(path) = &config.checkpoint_at {
+                            match state.save(path) {
+                                Ok(()) => {
+                                    // the checkpoint of the chunk's worker is not needed anymore
+                                    let worker_checkpoint = worker_checkpoint_path(path, chunk_index);
+
+                                    if exists(&worker_checkpoint) {
+                                        if let Err(e) = remove_file(&worker_checkpoint) {
+                                            write_log(
+                                                config.write_log_at.clone(),
+                                                "master",
+                                                &format!("failed to remove checkpoint {worker_checkpoint}: {e}"),
+                                            );
+                                        }
+                                    }
+
+                                    write_log(
+                                        config.write_log_at.clone(),
+                                        "master",
+                                        &format!("saved checkpoint at {path} ({} chunks are done)", state.finished_chunks.len()),
+                                    );
+                                },
+                                Err(e) => {
+                                    write_log(
+                                        config.write_log_at.clone(),
+                                        "master",
+                                        &format!("failed to save checkpoint at {path}: {e}"),
+                                    );
+                                },
+                            }
+                        }
+                    },
+                    // the other workers stop by themselves
+                    MessageToMain::Error(chunk_index, e) => {
+                        write_log(
+                            config.write_log_at.clone(),
+                            "master",
+                            &format!("chunk {chunk_index} failed, so it stops: {e}"),
+                        );
+
+                        return Err(e);
                     },
                     MessageToMain::Done => {
                         done += 1;
@@ -182,7 +393,7 @@ pub fn construct_dictionary_from_dir(config: DictionaryConfig) -> Result<Diction
             if has_update {
                 write_string(
                     path,
-                    &format!("{result:?}"),
+                    &format!("{:?}", state.result),
                     WriteMode::CreateOrTruncate,
                 ).unwrap();
 
@@ -207,95 +418,631 @@ pub fn construct_dictionary_from_dir(config: DictionaryConfig) -> Result<Diction
         "Goodbye from master!",
     );
 
-    Ok(result)
+    Ok(state.result)
+}
+
+/// ```nohighlight
+/// # bpe-rs master checkpoint
+/// chunks 0 1 3
+/// snapshots 300 1000
+/// # bpe-rs dictionary
+/// ...
+/// ```
+/// The indexes of the finished chunks and the sizes of the snapshots, followed by the dictionary files of
+/// the merged result and the merged snapshots.
+pub const MASTER_CHECKPOINT_HEADER: &str = "# bpe-rs master checkpoint";
+
+// state of `construct_dictionary_from_dir`
+#[derive(Debug, PartialEq)]
+struct MasterCheckpoint {
+    result: Dictionary,
+
+    // <size, merged snapshots>
+    snapshots: HashMap<usize, Dictionary>,
+
+    // indexes of the chunks that are already merged to `result` and `snapshots`
+    finished_chunks: HashSet<usize>,
+}
+
+impl MasterCheckpoint {
+    fn new(config: &DictionaryConfig) -> Self {
+        MasterCheckpoint {
+            result: MasterCheckpoint::empty_dictionary(config),
+            snapshots: HashMap::new(),
+            finished_chunks: HashSet::new(),
+        }
+    }
+
+    fn merge_chunk(
+        &mut self,
+        chunk_index: usize,
+        dictionary: &Dictionary,
+        snapshots: &[(usize, Dictionary)],
+        config: &DictionaryConfig,
+    ) {
+        self.result.merge(dictionary);
+
+        for (size, snapshot) in snapshots.iter() {
+            self.snapshots.entry(*size).or_insert_with(
+                || MasterCheckpoint::empty_dictionary(config)
+            ).merge(snapshot);
+        }
+
+        self.finished_chunks.insert(chunk_index);
+    }
+
+    // chunks are merged to it
+    fn empty_dictionary(config: &DictionaryConfig) -> Dictionary {
+        let mut result = Dictionary::from_words(HashMap::new(), config.model);
+        result.set_normalizers(config.normalizers.clone());
+        result
+    }
+
+    // Everything is in a file, so that a crash never leaves a part of it.
+    // Like `Checkpoint::save`, it writes to a temporary file first.
+    fn save(&self, path: &str) -> Result<(), FileError> {
+        let mut chunks = self.finished_chunks.iter().collect::<Vec<_>>();
+        chunks.sort();
+
+        let mut sizes = self.snapshots.keys().collect::<Vec<_>>();
+        sizes.sort();
+
+        let mut s = format!(
+            "{MASTER_CHECKPOINT_HEADER}\nchunks{}\nsnapshots{}\n{}",
+            chunks.iter().map(|index| format!(" {index}")).collect::<String>(),
+            sizes.iter().map(|size| format!(" {size}")).collect::<String>(),
+            self.result.to_text(),
+        );
+
+        for size in sizes.iter() {
+            s = format!("{s}{}", self.snapshots[size].to_text());
+        }
+
+        let tmp_path = format!("{path}.tmp");
+        write_string(&tmp_path, &s, WriteMode::CreateOrTruncate)?;
+        rename(&tmp_path, path)
+    }
+
+    fn load(path: &str) -> Result<Self, FileError> {
+        let s = read_string(path)?;
+        let invalid_file = || FileError::unknown(
+            String::from("invalid master checkpoint file"),
+            Some(path.to_string()),
+        );
+
+        let mut lines = s.splitn(4, '\n');
+
+        if lines.next() != Some(MASTER_CHECKPOINT_HEADER) {
+            return Err(invalid_file());
+        }
+
+        let parse_list = |line: Option<&str>, name: &str| -> Result<Vec<usize>, FileError> {
+            match line.map(|line| line.split(' ').collect::<Vec<_>>()).as_deref() {
+                Some([first, numbers @ ..]) if *first == name => numbers.iter().map(
+                    |n| n.parse::<usize>().map_err(|_| invalid_file())
+                ).collect(),
+                _ => Err(invalid_file()),
+            }
+        };
+
+        let finished_chunks = parse_list(lines.next(), "chunks")?.into_iter().collect();
+        let sizes = parse_list(lines.next(), "snapshots")?;
+
+        // a dictionary file always starts with `HEADER`, which cannot appear anywhere else
+        let mut dictionaries = lines.next().unwrap_or("").split(HEADER).skip(1).map(
+            |body| Dictionary::from_text(&format!("{HEADER}{body}"), path)
+        ).collect::<Result<Vec<_>, _>>()?.into_iter();
+
+        let result = dictionaries.next().ok_or_else(invalid_file)?;
+
+        if dictionaries.len() != sizes.len() {
+            return Err(invalid_file());
+        }
+
+        Ok(MasterCheckpoint {
+            result,
+            snapshots: sizes.into_iter().zip(dictionaries).collect(),
+            finished_chunks,
+        })
+    }
+}
+
+pub fn worker_checkpoint_path(path: &str, chunk_index: usize) -> String {
+    format!("{path}.chunk{chunk_index}")
 }
 
 pub fn construct_dictionary(
     bytes: &[u8],
     config: DictionaryConfig,
 ) -> Dictionary {
+    let snapshot_at = config.snapshot_at.clone();
+    let (result, snapshots) = construct_dictionary_with_snapshots(bytes, config);
+
+    if let Some(path) = &snapshot_at {
+        for (size, snapshot) in snapshots.iter() {
+            snapshot.save(&snapshot_path(path, *size)).unwrap();
+        }
+    }
+
+    result
+}
+
+/// `construct_dictionary` with the concatenation of `files`. See `Trainer::from_files`.\
+/// `Model::Unigram` and `Model::WordPiece` read all the files at once.
+pub fn construct_dictionary_from_files(
+    files: &[String],
+    config: DictionaryConfig,
+) -> Result<Dictionary, FileError> {
+    let snapshot_at = config.snapshot_at.clone();
+    let (result, snapshots) = construct_dictionary_with_snapshots_from_files(files, config)?;
+
+    if let Some(path) = &snapshot_at {
+        for (size, snapshot) in snapshots.iter() {
+            snapshot.save(&snapshot_path(path, *size))?;
+        }
+    }
+
+    Ok(result)
+}
+
+pub(crate) fn construct_dictionary_with_snapshots_from_files(
+    files: &[String],
+    config: DictionaryConfig,
+) -> Result<(Dictionary, Vec<(usize, Dictionary)>), FileError> {
+    if config.model != Model::Bpe {
+        let bytes = merge_files(files.to_vec(), config.dir_option.file_separator);
+        return Ok(construct_dictionary_with_snapshots(&bytes, config));
+    }
+
+    if let Some(path) = &config.write_log_at {
+        initialize_log_file(path, false)?;
+    }
+
+    let mut trainer = Trainer::from_files(files, config)?;
+    while trainer.next_merge().is_some() {}
+
+    if let Some(e) = trainer.error() {
+        return Err(e.clone());
+    }
+
+    Ok(trainer.finish_with_snapshots())
+}
+
+pub fn snapshot_path(path: &str, size: usize) -> String {
+    format!("{path}.{size}")
+}
+
+/// It returns the dictionaries at `snapshot_sizes` (in ascending order), as well as the final one.\
+/// A snapshot is the same as the result of a run whose `dictionary_size` is the size of the snapshot.
+/// Snapshots are only for `Model::Bpe`.\
+/// It panics if writing `disk_backed_at` fails in the middle of the training: use `construct_dictionary_from_files`
+/// to get the error instead.
+pub fn construct_dictionary_with_snapshots(
+    bytes: &[u8],
+    config: DictionaryConfig,
+) -> (Dictionary, Vec<(usize, Dictionary)>) {
     if let Some(path) = &config.write_log_at {
         initialize_log_file(path, false).unwrap();
     }
 
+    match config.model {
+        Model::Bpe => {
+            let write_log_at = config.write_log_at.clone();
+            let mut trainer = Trainer::new(bytes, config);
+            while trainer.next_merge().is_some() {}
+
+            // the units may be broken, so there's no result
+            if let Some(e) = trainer.error() {
+                write_log(write_log_at, "trainer", &format!("failed to write the units to disk: {e}"));
+                panic!("failed to write the units to disk: {e}");
+            }
+
+            trainer.finish_with_snapshots()
+        },
+        Model::Unigram | Model::WordPiece => {
+            let normalized = normalize(bytes, &config.normalizers).bytes;
+            let mut result = if config.model == Model::Unigram {
+                construct_unigram_dictionary(&normalized, &config)
+            } else {
+                construct_wordpiece_dictionary(&normalized, &config)
+            };
+
+            result.set_normalizers(config.normalizers.clone());
+            (result, vec![])
+        },
+    }
+}
+
+// before any merge (except the ones of `initial_dictionary` and `forced_tokens`)
+fn initial_state(bytes: &[u8], config: &DictionaryConfig) -> Checkpoint {
     let mut unit_map = default_unit_map();
-    let mut units = bytes_to_units(bytes);
+    let mut allocator = UnitAllocator::new();
+    let units = match config.character_coverage {
+        Some(coverage) => Units::from_units(chars_to_units(bytes, coverage, &mut unit_map, &mut allocator)),
+        None => Units::from_bytes(bytes),
+    };
 
-    loop {
-        let (_units, nothing_to_compress) = step(&units, &mut unit_map, config.minimum_appearance.unwrap_or(2), config.ultimate_separator);
-        units = _units;
+    // units in memory never fail
+    apply_initial_merges(units, unit_map, allocator, bytes.len(), config).unwrap()
+}
 
-        if nothing_to_compress || units.len() <= MINIMUN_STRING_LENGTH {
-            remove_unnecessary_units_in_map(&units, &mut unit_map, config.keep_single_byte_tokens);
-            break;
+/// `initial_state`, but it reads `files` block by block. If there's `disk_backed_at`, the units are directly written there.
+fn initial_state_from_files(files: &[String], config: &DictionaryConfig) -> Result<Checkpoint, FileError> {
+    let mut unit_map = default_unit_map();
+    let mut allocator = UnitAllocator::new();
+
+    // it needs one more pass to count the characters
+    let char_units = match config.character_coverage {
+        Some(coverage) => {
+            let mut char_counts = HashMap::new();
+            let mut total = 0;
+            for_each_block(files, config, STREAMING_BLOCK_SIZE, |block| { total += count_chars(block, &mut char_counts); })?;
+
+            assign_char_units(char_counts, total, coverage, &mut unit_map, &mut allocator)
+        },
+        None => HashMap::new(),
+    };
+
+    let mut input_length = 0;
+    let units = match &config.disk_backed_at {
+        Some(path) => {
+            let mut writer = MappedUnits::create(path)?;
+            let mut error = Ok(());
+
+            for_each_block(files, config, STREAMING_BLOCK_SIZE, |block| {
+                input_length += block.len();
+                push_char_units(block, &char_units, |unit| {
+                    if error.is_ok() {
+                        error = writer.push(unit);
+                    }
+                });
+            })?;
+
+            error?;
+            Units::Mapped(writer.finish()?)
+        },
+        None => {
+            let mut units = Units::Narrow(vec![]);
+
+            for_each_block(files, config, STREAMING_BLOCK_SIZE, |block| {
+                input_length += block.len();
+                push_char_units(block, &char_units, |unit| { units.push(unit); });
+            })?;
+
+            units
+        },
+    };
+
+    apply_initial_merges(units, unit_map, allocator, input_length, config)
+}
+
+/// It reads `files` in blocks that end at a newline, and applies `normalizers` to each block.
+/// `dir_option.file_separator` goes between the files.\
+/// The whitespaces at the end of a block are carried over to the next block (even across files), so that
+/// `Normalizer::CollapseWhitespace` sees a run of whitespaces at once.
+fn for_each_block<F: FnMut(&[u8])>(
+    files: &[String],
+    config: &DictionaryConfig,
+    block_size: usize,
+    mut f: F,
+) -> Result<(), FileError> {
+    let mut first_block = true;
+    let mut emit = |block: &[u8]| {
+        if config.normalizers.is_empty() {
+            f(block);
+            return;
+        }
+
+        let normalized = normalize(block, &config.normalizers);
+
+        // `WhitespaceMarker` is prepended only once, at the very beginning of the input
+        let skip = if first_block {
+            0
+        } else {
+            normalized.alignments.iter().take_while(|alignment| **alignment == (0, 0)).count()
+        };
+
+        first_block = false;
+        f(&normalized.bytes[skip..]);
+    };
+
+    let mut block = vec![];
+
+    for (index, path) in files.iter().enumerate() {
+        if index > 0 {
+            if let Some(separator) = config.dir_option.file_separator {
+                block.push(separator);
+            }
         }
 
-        if unit_map.len() >= config.dictionary_size {
-            remove_unnecessary_units_in_map(&units, &mut unit_map, config.keep_single_byte_tokens);
+        for_each_chunk(path, block_size, |chunk| {
+            block.extend_from_slice(chunk);
+
+            if block.len() < block_size {
+                return;
+            }
+
+            // a newline, otherwise the beginning of the last character
+            let end = match block.iter().rposition(|byte| *byte == b'\n') {
+                Some(index) => index + 1,
+                None => block.iter().rposition(|byte| byte & 0b1100_0000 != 0b1000_0000).unwrap_or(0),
+            };
+            let end = trailing_whitespaces(&block[..end]);
+
+            // if there's no place to end the block, it reads more
+            if end > 0 {
+                emit(&block[..end]);
+                block.drain(..end);
+            }
+        })?;
+    }
+
+    if !block.is_empty() {
+        emit(&block);
+    }
+
+    Ok(())
+}
+
+// where the whitespaces at the end of `bytes` begin
+fn trailing_whitespaces(bytes: &[u8]) -> usize {
+    let mut result = 0;
+    let mut index = 0;
+
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            index += c.len_utf8();
 
-            if unit_map.len() >= config.dictionary_size {
-                break;
+            if !c.is_whitespace() {
+                result = index;
             }
         }
+
+        index += chunk.invalid().len();
+
+        if !chunk.invalid().is_empty() {
+            result = index;
+        }
     }
 
-    Dictionary::from_units(&units, &unit_map)
+    result
 }
 
-/// count_pairs + assign_pair_to_new_unit\
-/// It also inserts an entry to `unit_map`
+// before any merge: `initial_dictionary` and `forced_tokens`
+fn apply_initial_merges(
+    mut units: Units,
+    mut unit_map: UnitMapInternal,
+    mut allocator: UnitAllocator,
+    input_length: usize,
+    config: &DictionaryConfig,
+) -> Result<Checkpoint, FileError> {
+    let threads = threads_for(units.len(), config.thread_count);
+    let mut merges = vec![];
+
+    // units of `initial_dictionary` and `forced_tokens` are never removed
+    let mut protected = HashSet::new();
+
+    let mut units_by_bytes = unit_map.iter().map(
+        |(unit, bytes)| (bytes.to_vec(), *unit)
+    ).collect::<HashMap<_, _>>();
+
+    if let Some(initial_dictionary) = &config.initial_dictionary {
+        // Units that can appear in the input: bytes (or characters), and what the merges make of them.
+        // The other ones (e.g. characters of a dictionary with `character_coverage`, or tokens of
+        // a `Model::Unigram` dictionary) get new units, but they never appear.
+        let mut reachable = unit_map.keys().copied().collect::<HashSet<_>>();
+        let mut unreachable = vec![];
+
+        for (w1, w2) in initial_dictionary.merges().iter() {
+            let c1 = get_or_insert_unit(w1, &mut unit_map, &mut units_by_bytes, &mut allocator);
+            let c2 = get_or_insert_unit(w2, &mut unit_map, &mut units_by_bytes, &mut allocator);
+            let pair = into_pair(c1, c2);
+            let new_unit = assign_new_unit(pair, &mut unit_map, &mut allocator);
+
+            if reachable.contains(&c1) && reachable.contains(&c2) {
+                reachable.insert(new_unit);
+            }
+
+            units_by_bytes.insert([w1.as_slice(), w2.as_slice()].concat(), new_unit);
+            units.replace_pair(pair, new_unit, threads)?;
+            merges.push((w1.to_vec(), w2.to_vec()));
+        }
+
+        let mut words = initial_dictionary.iter().map(|(word, _)| word).collect::<Vec<_>>();
+        words.sort();
+
+        for word in words.into_iter() {
+            let unit = get_or_insert_unit(word, &mut unit_map, &mut units_by_bytes, &mut allocator);
+
+            if !reachable.contains(&unit) {
+                unreachable.push(String::from_utf8_lossy(word).to_string());
+            }
+
+            protected.insert(unit);
+        }
+
+        if !unreachable.is_empty() {
+            write_log(
+                config.write_log_at.clone(),
+                "initial_dictionary",
+                &format!(
+                    "{} tokens of `initial_dictionary` can never appear, because they're not made from the bytes (or characters) of the input: {:?}",
+                    unreachable.len(),
+                    &unreachable[..unreachable.len().min(16)],
+                ),
+            );
+        }
+    }
+
+    for token in config.forced_tokens.iter() {
+        if token.is_empty() {
+            continue;
+        }
+
+        if let Some(unit) = units_by_bytes.get(token) {
+            protected.insert(*unit);
+            continue;
+        }
+
+        // characters that are units (`character_coverage`), otherwise bytes
+        let mut symbols = vec![];
+
+        for chunk in token.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                let mut buffer = [0; 4];
+                let c = c.encode_utf8(&mut buffer).as_bytes();
+
+                if units_by_bytes.contains_key(c) {
+                    symbols.push(c.to_vec());
+                }
+
+                else {
+                    symbols.extend(c.iter().map(|byte| vec![*byte]));
+                }
+            }
+
+            symbols.extend(chunk.invalid().iter().map(|byte| vec![*byte]));
+        }
+
+        // it's merged from left to right: `a` + `b`, `ab` + `c`, ...
+        let mut prefix = symbols[0].clone();
+        let mut prefix_unit = *units_by_bytes.get(&prefix).unwrap();
+
+        for symbol in symbols[1..].iter() {
+            let merged = [prefix.as_slice(), symbol.as_slice()].concat();
+
+            prefix_unit = match units_by_bytes.get(&merged) {
+                Some(unit) => *unit,
+                None => {
+                    let pair = into_pair(prefix_unit, *units_by_bytes.get(symbol).unwrap());
+                    let new_unit = assign_new_unit(pair, &mut unit_map, &mut allocator);
+
+                    units_by_bytes.insert(merged.clone(), new_unit);
+                    units.replace_pair(pair, new_unit, threads)?;
+                    merges.push((prefix, symbol.to_vec()));
+                    new_unit
+                },
+            };
+            prefix = merged;
+        }
+
+        protected.insert(prefix_unit);
+    }
+
+    Ok(Checkpoint { merges, unit_map, units, protected, allocator, input_length })
+}
+
+/// count_pairs + assign_pair_to_new_unit, in place\
+/// It also inserts an entry to `unit_map`. It can only fail with `Units::Mapped`.
 pub fn step(
-    s: &[Unit],
+    s: &mut Units,
     unit_map: &mut UnitMapInternal,
-    minimum_appearance: usize,
-    ultimate_separator: Option<u8>,
-) -> (Vec<Unit>, bool) {  // (new_s, less than minimum_appearance)
-    let pairs = count_pairs(s);
+    allocator: &mut UnitAllocator,
+    config: &DictionaryConfig,
+) -> Result<Option<(Pair, usize)>, FileError> {  // (merged pair, its count) (None if less than minimum_appearance)
+    let threads = threads_for(s.len(), config.thread_count);
+    let pairs = s.count_pairs(threads);
+    let minimum_appearance = config.minimum_appearance.unwrap_or(2);
+
+    let unit_counts = if config.scoring.uses_unit_counts() {
+        s.counts()
+    } else {
+        HashMap::new()
+    };
 
-    let mut curr_best_pair = 0;
-    let mut curr_best_count = 0;
+    // (pair, count, score)
+    let mut curr_best: Option<(Pair, usize, f64)> = None;
 
     for (pair, count) in pairs.iter() {
-        if *count > curr_best_count {
-            if let Some(u) = ultimate_separator {
-                let u = u as Unit;
-                let (c1, c2) = from_pair(*pair);
+        if *count < minimum_appearance {
+            continue;
+        }
 
-                if u == c1 || u == c2 {
-                    continue;
-                }
+        let (c1, c2) = from_pair(*pair);
+
+        if let Some(u) = config.ultimate_separator {
+            let u = u as Unit;
+
+            if u == c1 || u == c2 {
+                continue;
             }
+        }
 
-            curr_best_count = *count;
-            curr_best_pair = *pair;
+        let left = unit_map.get(&c1).unwrap().as_slice();
+        let right = unit_map.get(&c2).unwrap().as_slice();
+        let score = config.scoring.score(&MergeCandidate {
+            left,
+            right,
+            count: *count,
+            left_count: unit_counts.get(&c1).copied().unwrap_or(0),
+            right_count: unit_counts.get(&c2).copied().unwrap_or(0),
+            total: s.len(),
+        });
+
+        // a `MergeScorer` may return NaN
+        if score.is_nan() {
+            continue;
         }
-    }
 
-    if curr_best_count < minimum_appearance {
-        return (s.to_vec(), true);
+        if let Some((best_pair, _, best_score)) = curr_best {
+            if score < best_score {
+                continue;
+            }
+
+            // `HashMap` iterates in a random order, so ties have to be broken explicitly
+            if score == best_score && !pair_precedes(*pair, best_pair, unit_map) {
+                continue;
+            }
+        }
+
+        if !config.allows_token(&[left, right].concat()) {
+            continue;
+        }
+
+        curr_best = Some((*pair, *count, score));
     }
 
-    let new_unit = assign_new_unit(curr_best_pair, unit_map, None);
+    let Some((curr_best_pair, curr_best_count, _)) = curr_best else {
+        return Ok(None);
+    };
+
+    let new_unit = assign_new_unit(curr_best_pair, unit_map, allocator);
+    s.replace_pair(curr_best_pair, new_unit, threads)?;
 
-    (assign_pair_to_new_unit(s, curr_best_pair, new_unit), false)
+    Ok(Some((curr_best_pair, curr_best_count)))
 }
 
-pub fn remove_unnecessary_units_in_map(
-    units: &[Unit],
+/// Tie-breaker for pairs with the same count.\
+/// It compares the bytes of the pairs lexicographically, then the units.
+pub fn pair_precedes(p1: Pair, p2: Pair, unit_map: &UnitMapInternal) -> bool {
+    let (a1, a2) = from_pair(p1);
+    let (b1, b2) = from_pair(p2);
+    let bytes1 = unit_map.get(&a1).unwrap().iter().chain(unit_map.get(&a2).unwrap().iter());
+    let bytes2 = unit_map.get(&b1).unwrap().iter().chain(unit_map.get(&b2).unwrap().iter());
+
+    match bytes1.cmp(bytes2) {
+        Ordering::Equal => p1 < p2,
+        ordering => ordering == Ordering::Less,
+    }
+}
+
+pub fn remove_unnecessary_units_in_map<I: IntoIterator<Item = Unit>>(
+    units: I,
     unit_map: &mut UnitMapInternal,
     keep_single_byte_tokens: bool,
+    protected: &HashSet<Unit>,
 ) -> usize {  // it returns how many units it removed
     let mut unit_set = HashSet::with_capacity(unit_map.len());
 
-    for c in units.iter() {
-        unit_set.insert(*c);
+    for c in units {
+        unit_set.insert(c);
     }
 
     let mut units_to_remove = vec![];
 
     for unit in unit_map.keys() {
         if !unit_set.contains(unit) {
-            if keep_single_byte_tokens && *unit < 256 {
+            if keep_single_byte_tokens && *unit < 256 || protected.contains(unit) {
                 continue;
             }
 
@@ -314,31 +1061,12 @@ pub fn remove_unnecessary_units_in_map(
 pub fn assign_new_unit(
     pair: Pair,
     unit_map: &mut UnitMapInternal,
-
-    // if you have computed this in advance, you can directly provide this
-    // otherwise it would calculate which unit to use.
-    // if the provided unit is already being used, it would choose another one
-    new_unit: Option<Unit>,
+    allocator: &mut UnitAllocator,
 ) -> Unit {
-    let new_unit = match new_unit {
-        Some(new_unit) if !unit_map.contains_key(&new_unit) => new_unit,
-        _ => {
-            let mut new_unit = 0;
-
-            // it makes sure that multi-byte tokens are always assigned to < 255
-            for i in 256..Unit::MAX {
-                if !unit_map.contains_key(&i) {
-                    new_unit = i;
-                    break;
-                }
-            }
-
-            new_unit
-        },
-    };
+    let new_unit = allocator.allocate();
 
     let (c1, c2) = from_pair(pair);
-    let new_bytes = vec![
+    let new_bytes = [
         unit_map.get(&c1).unwrap().to_vec(),
         unit_map.get(&c2).unwrap().to_vec(),
     ].concat();
@@ -347,11 +1075,46 @@ pub fn assign_new_unit(
     new_unit
 }
 
-pub fn count_pairs(s: &[Unit]) -> HashMap<Pair, usize> {
+fn get_or_insert_unit(
+    bytes: &[u8],
+    unit_map: &mut UnitMapInternal,
+    units_by_bytes: &mut HashMap<Vec<u8>, Unit>,
+    allocator: &mut UnitAllocator,
+) -> Unit {
+    match units_by_bytes.get(bytes) {
+        Some(unit) => *unit,
+        None => {
+            let unit = allocator.allocate();
+            unit_map.insert(unit, bytes.into());
+            units_by_bytes.insert(bytes.to_vec(), unit);
+            unit
+        },
+    }
+}
+
+/// It counts what `assign_pair_to_new_unit` would replace: pairs of the same unit don't overlap,
+/// so `aaaa` has 2 `aa`s, not 3.
+pub fn count_pairs<T: UnitLike>(s: &[T]) -> HashMap<Pair, usize> {
     let mut result = HashMap::with_capacity(1024);
 
+    // the previous window was a counted pair of the same unit
+    let mut overlapping = false;
+
     for p in s.windows(2) {
-        let curr_pair = into_pair(p[0], p[1]);
+        if p[0] == p[1] {
+            if overlapping {
+                overlapping = false;
+                continue;
+            }
+
+            overlapping = true;
+        }
+
+        else {
+            overlapping = false;
+        }
+
+        let curr_pair = into_pair(p[0].to_unit(), p[1].to_unit());
 
         match result.get_mut(&curr_pair) {
             Some(n) => {
@@ -367,40 +1130,35 @@ pub fn count_pairs(s: &[Unit]) -> HashMap<Pair, usize> {
 }
 
 pub fn assign_pair_to_new_unit(s: &[Unit], pair: Pair, new_unit: Unit) -> Vec<Unit> {
-    let mut result = Vec::with_capacity(s.len());
-    let (c1, c2) = from_pair(pair);
-    let mut expecting_c2 = false;
-
-    for c in s.iter() {
-        if expecting_c2 {
-            if *c == c2 {
-                result.push(new_unit);
-                expecting_c2 = false;
-            }
-
-            else if *c == c1 {
-                result.push(c1);
-            }
+    let mut result = s.to_vec();
+    let len = replace_pair_in_place(&mut result, pair, new_unit);
+    result.truncate(len);
 
-            else {
-                result.push(c1);
-                result.push(*c);
-                expecting_c2 = false;
-            }
-        }
+    result
+}
 
-        else if *c == c1 {
-            expecting_c2 = true;
+/// It replaces `pair` with `new_unit` from left to right, and returns the new length.\
+/// `new_unit` has to fit in `T`.
+pub fn replace_pair_in_place<T: UnitLike>(s: &mut [T], pair: Pair, new_unit: Unit) -> usize {
+    let (c1, c2) = from_pair(pair);
+    let new_unit = T::from_unit(new_unit);
+    let mut read = 0;
+    let mut write = 0;
+
+    // `write` never passes `read`
+    while read < s.len() {
+        if s[read].to_unit() == c1 && read + 1 < s.len() && s[read + 1].to_unit() == c2 {
+            s[write] = new_unit;
+            read += 2;
         }
 
         else {
-            result.push(*c);
+            s[write] = s[read];
+            read += 1;
         }
-    }
 
-    if expecting_c2 {
-        result.push(c1);
+        write += 1;
     }
 
-    result
+    write
 }
@@ -0,0 +1,43 @@
+use super::{Unit, UnitMapInternal};
+
+/// It hands out new units in constant time. A unit is never handed out twice, even after it's removed
+/// from the unit map, so a unit made by a later merge is always larger than the ones made before it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnitAllocator {
+    next: Unit,
+}
+
+impl UnitAllocator {
+    /// 0 ~ 255 are single bytes, so it starts from 256.
+    pub fn new() -> Self {
+        UnitAllocator { next: 256 }
+    }
+
+    /// It never hands out a unit in `unit_map`.
+    pub fn after(unit_map: &UnitMapInternal) -> Self {
+        let next = unit_map.keys().max().map(|unit| unit + 1).unwrap_or(0).max(256);
+        UnitAllocator { next }
+    }
+
+    pub fn starting_at(next: Unit) -> Self {
+        UnitAllocator { next: next.max(256) }
+    }
+
+    pub fn allocate(&mut self) -> Unit {
+        let result = self.next;
+        self.next = self.next.checked_add(1).expect("ran out of units");
+
+        result
+    }
+
+    /// the unit that `allocate` would return
+    pub fn peek(&self) -> Unit {
+        self.next
+    }
+}
+
+impl Default for UnitAllocator {
+    fn default() -> Self {
+        UnitAllocator::new()
+    }
+}
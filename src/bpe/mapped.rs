@@ -0,0 +1,120 @@
+use super::Unit;
+use crate::files::{FileError, remove_file};
+use memmap2::MmapMut;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+/// `Unit`s in a memory-mapped file. The OS pages them in and out, so they don't have to fit in memory.\
+/// The file is a working file: it's in the native byte order, and it's removed when this is dropped.
+#[derive(Debug)]
+pub struct MappedUnits {
+    path: String,
+    file: File,
+
+    // `None` if the file is empty: an empty file cannot be mapped
+    mmap: Option<MmapMut>,
+
+    // the file may be longer than this
+    len: usize,
+}
+
+impl MappedUnits {
+    /// It writes units to `path` (truncating the old one). Call `MappedUnitsWriter::finish` to map them.
+    pub fn create(path: &str) -> Result<MappedUnitsWriter, FileError> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path).map_err(
+            |e| FileError::from_std(e, path)
+        )?;
+
+        Ok(MappedUnitsWriter {
+            path: path.to_string(),
+            writer: BufWriter::new(file),
+            len: 0,
+        })
+    }
+
+    pub fn write<I: Iterator<Item = Unit>>(path: &str, units: I) -> Result<Self, FileError> {
+        let mut writer = MappedUnits::create(path)?;
+
+        for unit in units {
+            writer.push(unit)?;
+        }
+
+        writer.finish()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn as_slice(&self) -> &[Unit] {
+        match &self.mmap {
+            // a mapping is aligned to a page, so there's no prefix
+            Some(mmap) => unsafe { &mmap.align_to::<Unit>().1[..self.len] },
+            None => &[],
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Unit] {
+        match &mut self.mmap {
+            Some(mmap) => unsafe { &mut mmap.align_to_mut::<Unit>().1[..self.len] },
+            None => &mut [],
+        }
+    }
+
+    /// If it gets much shorter than the file, the file is also truncated to give the disk space back.
+    pub fn truncate(&mut self, len: usize) -> Result<(), FileError> {
+        self.len = self.len.min(len);
+
+        let capacity = self.mmap.as_ref().map(|mmap| mmap.len() / 4).unwrap_or(0);
+
+        // the old mapping is kept until the new one is ready, so the units are still there if it fails
+        if self.len < capacity / 2 {
+            self.file.set_len((self.len * 4) as u64).map_err(|e| FileError::from_std(e, &self.path))?;
+            self.map()?;
+        }
+
+        Ok(())
+    }
+
+    // `mmap` is not touched if it fails
+    fn map(&mut self) -> Result<(), FileError> {
+        self.mmap = if self.len == 0 {
+            None
+        } else {
+            // nobody else is supposed to touch the working file
+            Some(unsafe { MmapMut::map_mut(&self.file) }.map_err(|e| FileError::from_std(e, &self.path))?)
+        };
+
+        Ok(())
+    }
+}
+
+pub struct MappedUnitsWriter {
+    path: String,
+    writer: BufWriter<File>,
+    len: usize,
+}
+
+impl MappedUnitsWriter {
+    pub fn push(&mut self, unit: Unit) -> Result<(), FileError> {
+        self.writer.write_all(&unit.to_ne_bytes()).map_err(|e| FileError::from_std(e, &self.path))?;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<MappedUnits, FileError> {
+        let file = self.writer.into_inner().map_err(|e| FileError::from_std(e.into_error(), &self.path))?;
+        let mut result = MappedUnits { path: self.path, file, mmap: None, len: self.len };
+        result.map()?;
+
+        Ok(result)
+    }
+}
+
+impl Drop for MappedUnits {
+    fn drop(&mut self) {
+        self.mmap = None;
+        let _ = remove_file(&self.path);
+    }
+}
@@ -0,0 +1,115 @@
+use super::{Pair, Unit, UnitLike, count_pairs, from_pair, replace_pair_in_place};
+use std::collections::HashMap;
+use std::thread;
+
+/// A thread gets at least this many units. Otherwise, spawning threads costs more than it saves.
+pub const MINIMUM_CHUNK_LENGTH: usize = 1 << 16;
+
+/// How many threads to use for `len` units, when `thread_count` threads are available.
+pub fn threads_for(len: usize, thread_count: Option<usize>) -> usize {
+    thread_count.unwrap_or(1).min(len / MINIMUM_CHUNK_LENGTH).max(1)
+}
+
+/// Same as `count_pairs`, but with `threads` threads.
+pub fn count_pairs_parallel<T: UnitLike>(s: &[T], threads: usize) -> HashMap<Pair, usize> {
+    if threads < 2 {
+        return count_pairs(s);
+    }
+
+    // a run of the same unit is never split, so that the runs are counted the same way as `count_pairs`
+    let boundaries = split_at(s, threads, |s, i| s[i - 1] != s[i]);
+
+    let counts = thread::scope(|scope| {
+        let handles = boundaries.windows(2).map(
+            // a chunk also counts the pair on its right boundary
+            |w| scope.spawn(move || count_pairs(&s[w[0]..(w[1] + 1).min(s.len())]))
+        ).collect::<Vec<_>>();
+
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    let mut counts = counts.into_iter();
+    let mut result = counts.next().unwrap_or_default();
+
+    for chunk in counts {
+        for (pair, count) in chunk.into_iter() {
+            *result.entry(pair).or_insert(0) += count;
+        }
+    }
+
+    result
+}
+
+/// Same as `replace_pair_in_place`, but with `threads` threads. It returns the new length.
+pub fn replace_pair_in_place_parallel<T: UnitLike>(s: &mut [T], pair: Pair, new_unit: Unit, threads: usize) -> usize {
+    if threads < 2 {
+        return replace_pair_in_place(s, pair, new_unit);
+    }
+
+    // the replacement never carries anything over a unit that's not the first unit of the pair,
+    // so chunks that start after such a unit are replaced the same way as the serial one
+    let (c1, _) = from_pair(pair);
+    let boundaries = split_at(s, threads, |s, i| s[i - 1].to_unit() != c1);
+
+    let mut chunks = Vec::with_capacity(boundaries.len());
+    let mut rest = &mut s[..];
+
+    for w in boundaries.windows(2) {
+        let (chunk, r) = rest.split_at_mut(w[1] - w[0]);
+        chunks.push(chunk);
+        rest = r;
+    }
+
+    let lengths = thread::scope(|scope| {
+        let handles = chunks.into_iter().map(
+            |chunk| scope.spawn(move || replace_pair_in_place(chunk, pair, new_unit))
+        ).collect::<Vec<_>>();
+
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+    });
+
+    // each chunk shrank in place, so the gaps between them have to be closed
+    let mut result = 0;
+
+    for (w, len) in boundaries.windows(2).zip(lengths) {
+        s.copy_within(w[0]..(w[0] + len), result);
+        result += len;
+    }
+
+    result
+}
+
+/// Same as `assign_pair_to_new_unit`, but with `threads` threads.
+#[cfg(test)]
+pub fn assign_pair_to_new_unit_parallel(s: &[Unit], pair: Pair, new_unit: Unit, threads: usize) -> Vec<Unit> {
+    let mut result = s.to_vec();
+    let len = replace_pair_in_place_parallel(&mut result, pair, new_unit, threads);
+    result.truncate(len);
+
+    result
+}
+
+// `0, b1, b2, ..., s.len()`: each boundary `b` is moved forward until `is_boundary(s, b)`
+fn split_at<T, F: Fn(&[T], usize) -> bool>(s: &[T], chunks: usize, is_boundary: F) -> Vec<usize> {
+    let mut result = vec![0];
+    let chunk_length = s.len().div_ceil(chunks.max(1));
+
+    for i in 1..chunks {
+        let mut boundary = (i * chunk_length).max(*result.last().unwrap());
+
+        while boundary < s.len() && boundary > 0 && !is_boundary(s, boundary) {
+            boundary += 1;
+        }
+
+        if boundary >= s.len() {
+            break;
+        }
+
+        if boundary > *result.last().unwrap() {
+            result.push(boundary);
+        }
+    }
+
+    result.push(s.len());
+    result
+}
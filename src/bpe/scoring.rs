@@ -0,0 +1,100 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// How `step` chooses a pair to merge. The pair with the highest score is merged.
+/// Pairs that appear less than `minimum_appearance` times are never scored.
+#[derive(Clone, Default)]
+pub enum Scoring {
+    /// `count(ab)`
+    #[default]
+    Frequency,
+
+    /// `ln(count(ab) · total / (count(a) · count(b)))`
+    Pmi,
+
+    /// `count(ab) / (count(a) · count(b))`, which is what WordPiece uses
+    Likelihood,
+
+    /// `count(ab) · len(ab)`: it favours pairs that save more bytes of tokens
+    LengthWeighted,
+
+    Custom(Arc<dyn MergeScorer>),
+}
+
+/// A user-supplied scoring function. See `Scoring::Custom`.
+pub trait MergeScorer: Send + Sync {
+    fn score(&self, candidate: &MergeCandidate) -> f64;
+}
+
+pub struct MergeCandidate<'a> {
+    pub left: &'a [u8],
+    pub right: &'a [u8],
+
+    // how many times the pair appears
+    pub count: usize,
+    pub left_count: usize,
+    pub right_count: usize,
+
+    // length of the data, in units
+    pub total: usize,
+}
+
+impl Scoring {
+    pub fn score(&self, candidate: &MergeCandidate) -> f64 {
+        let count = candidate.count as f64;
+        let left_count = candidate.left_count.max(1) as f64;
+        let right_count = candidate.right_count.max(1) as f64;
+
+        match self {
+            Scoring::Frequency => count,
+            Scoring::Pmi => (count * candidate.total as f64 / (left_count * right_count)).ln(),
+            Scoring::Likelihood => count / (left_count * right_count),
+            Scoring::LengthWeighted => count * (candidate.left.len() + candidate.right.len()) as f64,
+            Scoring::Custom(scorer) => scorer.score(candidate),
+        }
+    }
+
+    // `step` doesn't have to count the units for these
+    pub(crate) fn uses_unit_counts(&self) -> bool {
+        !matches!(self, Scoring::Frequency | Scoring::LengthWeighted)
+    }
+}
+
+impl fmt::Debug for Scoring {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scoring::Frequency => write!(fmt, "Frequency"),
+            Scoring::Pmi => write!(fmt, "Pmi"),
+            Scoring::Likelihood => write!(fmt, "Likelihood"),
+            Scoring::LengthWeighted => write!(fmt, "LengthWeighted"),
+            Scoring::Custom(_) => write!(fmt, "Custom(..)"),
+        }
+    }
+}
+
+impl PartialEq for Scoring {
+    fn eq(&self, other: &Scoring) -> bool {
+        match (self, other) {
+            (Scoring::Frequency, Scoring::Frequency)
+            | (Scoring::Pmi, Scoring::Pmi)
+            | (Scoring::Likelihood, Scoring::Likelihood)
+            | (Scoring::LengthWeighted, Scoring::LengthWeighted) => true,
+            (Scoring::Custom(s1), Scoring::Custom(s2)) => Arc::ptr_eq(s1, s2),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Scoring {}
+
+impl Hash for Scoring {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+
+        // the same as `PartialEq`: it's the same scorer only if it's the same object
+        if let Scoring::Custom(scorer) = self {
+            (Arc::as_ptr(scorer) as *const () as usize).hash(state);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use super::*;
 use crate::files::read_bytes;
+use crate::normalizer::{Normalizer, marker_only_at_start};
+use crate::test_utils::{corpus, temp_path};
 
 #[test]
 fn unit_pair_roundtrip() {
@@ -64,3 +66,705 @@ fn dictionary_count_test_worker(file: &str, dictionary_size: usize) {
 
     assert_eq!(bytes.len(), sum);
 }
+
+// `HashMap`s iterate in a random order, but the merges must not depend on that
+#[test]
+fn deterministic_merges_test() {
+    let bytes = sample_corpus();
+    let config = DictionaryConfig::default()
+        .set_dictionary_size(300)
+        .set_minimum_appearance(Some(2))
+        .to_owned();
+
+    let result1 = construct_dictionary(&bytes, config.clone());
+    let result2 = construct_dictionary(&bytes, config.clone());
+
+    assert!(!result1.merges().is_empty());
+    assert_eq!(result1.merges(), result2.merges());
+}
+
+// lots of ties: every pair in `abcd...` appears the same number of times
+fn sample_corpus() -> Vec<u8> {
+    corpus(b". abcdefghijklmnopqrstuvwxyz ")
+}
+
+#[test]
+fn token_constraints_test() {
+    let mut bytes = sample_corpus();
+    bytes.extend_from_slice("\nfoo\nbar 가나다 가나다 가나다 가나다 abc123 abc123 abc123\n".repeat(16).as_bytes());
+
+    let result = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_dictionary_size(512)
+            .set_maximum_token_length(Some(5))
+            .set_respect_utf8_boundary(true)
+            .set_separate_letters_and_digits(true)
+            .set_whitespace_after_newline(true)
+            .to_owned(),
+    );
+
+    for (word, _) in result.iter() {
+        let s = String::from_utf8_lossy(word);
+
+        assert!(word.len() <= 5);
+        assert!(!(s.chars().any(|c| c.is_alphabetic()) && s.chars().any(|c| c.is_numeric())));
+        assert!(!s.contains("\nf") && !s.contains("\nb"));
+
+        // it can be a part of a character, but never crosses a character boundary
+        if let Err(e) = std::str::from_utf8(word) {
+            assert_eq!(e.valid_up_to(), 0);
+        }
+    }
+}
+
+#[test]
+fn character_coverage_test() {
+    let common = "가나다라 마바사 아자차카 타파하 ";
+    let mut s = common.repeat(32);
+    s.push('뷁');
+
+    let mut unit_map = default_unit_map();
+    let units = chars_to_units(s.as_bytes(), 0.99, &mut unit_map, &mut UnitAllocator::new());
+
+    // common characters are single units, and the rare one falls back to 3 bytes
+    assert_eq!(units.len(), common.chars().count() * 32 + 3);
+    assert_eq!(units_to_bytes(&units, &unit_map), s.as_bytes());
+
+    let result = construct_dictionary(
+        s.as_bytes(),
+        DictionaryConfig::default()
+            .set_dictionary_size(300)
+            .set_character_coverage(Some(0.99))
+            .to_owned(),
+    );
+
+    for (word, appearance) in result.iter() {
+        if *appearance > 0 && word.len() > 1 {
+            assert!(std::str::from_utf8(word).is_ok());
+        }
+    }
+}
+
+#[test]
+fn whitespace_marker_training_test() {
+    let bytes = sample_corpus();
+    let result = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_dictionary_size(400)
+            .add_normalizer(Normalizer::WhitespaceMarker)
+            .to_owned(),
+    );
+
+    assert!(result.get(&"▁the".as_bytes().to_vec()).is_some());
+
+    for (word, appearance) in result.iter() {
+        assert!(*appearance == 0 || !word.contains(&b' '));
+        assert!(marker_only_at_start(word));
+    }
+}
+
+#[test]
+fn initial_dictionary_test() {
+    let initial_dictionary = construct_dictionary(
+        &sample_corpus(),
+        DictionaryConfig::default()
+            .set_dictionary_size(300)
+            .to_owned(),
+    );
+
+    let mut bytes = sample_corpus();
+    bytes.extend_from_slice(b"Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(32).as_slice());
+
+    let result = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_dictionary_size(400)
+            .set_initial_dictionary(Some(initial_dictionary.clone()))
+            .to_owned(),
+    );
+
+    assert!(result.len() <= 400);
+    assert!(result.len() > initial_dictionary.len());
+    assert_eq!(&result.merges()[..initial_dictionary.merges().len()], initial_dictionary.merges());
+
+    for (word, _) in initial_dictionary.iter() {
+        assert!(result.get(word).is_some());
+    }
+
+    let mut sum = 0;
+
+    for (word, appearance) in result.iter() {
+        sum += word.len() * appearance;
+    }
+
+    assert_eq!(bytes.len(), sum);
+
+    // characters of `character_coverage` are not units of a byte-level run, so they're logged
+    let with_chars = construct_dictionary(
+        "가나다 가나다 라마 ".repeat(16).as_bytes(),
+        DictionaryConfig::default()
+            .set_dictionary_size(280)
+            .set_character_coverage(Some(1.0))
+            .to_owned(),
+    );
+    let log_path = temp_path("bpe_rs_initial_dictionary_test.log");
+    let result = construct_dictionary(
+        "가나다 라마".as_bytes(),
+        DictionaryConfig::default()
+            .set_dictionary_size(300)
+            .set_initial_dictionary(Some(with_chars.clone()))
+            .set_log_file(Some(log_path.clone()))
+            .to_owned(),
+    );
+
+    assert!(with_chars.iter().all(|(word, _)| result.get(word).is_some()));
+    assert!(read_string(&log_path).unwrap().contains("initial_dictionary"));
+    remove_file(&log_path).unwrap();
+}
+
+#[test]
+fn master_checkpoint_test() {
+    let path = temp_path("bpe_rs_master_checkpoint_test");
+    let config = DictionaryConfig::default().set_dictionary_size(300).set_snapshot_sizes(vec![280]).to_owned();
+    let (dictionary, snapshots) = construct_dictionary_with_snapshots(&sample_corpus(), config.clone());
+    let mut checkpoint = MasterCheckpoint::new(&config);
+
+    checkpoint.merge_chunk(0, &dictionary, &snapshots, &config);
+    checkpoint.merge_chunk(10, &dictionary, &[], &config);
+    checkpoint.save(&path).unwrap();
+
+    assert_eq!(MasterCheckpoint::load(&path).unwrap(), checkpoint);
+    assert_eq!(checkpoint.snapshots.len(), 1);
+    assert!(!exists(&format!("{path}.tmp")));
+
+    // a dictionary file is not a master checkpoint
+    dictionary.save(&path).unwrap();
+    assert!(MasterCheckpoint::load(&path).is_err());
+    remove_file(&path).unwrap();
+}
+
+#[test]
+fn dir_model_test() {
+    let dir = temp_path("bpe_rs_dir_model_test");
+    let bytes = sample_corpus();
+    let third = bytes.len() / 3;
+    crate::files::create_dir_all(&dir).unwrap();
+
+    for i in 0..3 {
+        let end = if i == 2 { bytes.len() } else { (i + 1) * third };
+        crate::files::write_bytes(&format!("{dir}/{i}.txt"), &bytes[(i * third)..end], WriteMode::CreateOrTruncate).unwrap();
+    }
+
+    for model in [Model::Unigram, Model::WordPiece] {
+        let result = construct_dictionary_from_dir(
+            DictionaryConfig::default()
+                .set_model(model)
+                .set_dictionary_size(300)
+                .set_dir(dir.clone())
+                .set_extension_to_read(String::from("txt"))
+                .set_file_chunk_size(1)
+                .set_worker_count(Some(2))
+                .to_owned(),
+        ).unwrap();
+
+        assert_eq!(result.model(), model);
+        assert!(!result.is_empty());
+
+        // it doesn't panic
+        result.tokenize(b"the lazy fox");
+    }
+
+    crate::files::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dir_error_test() {
+    let dir = temp_path("bpe_rs_dir_error_test");
+    crate::files::create_dir_all(&dir).unwrap();
+    crate::files::write_bytes(&format!("{dir}/0.txt"), &sample_corpus(), WriteMode::CreateOrTruncate).unwrap();
+
+    // the working file of the worker cannot be created
+    let result = construct_dictionary_from_dir(
+        DictionaryConfig::default()
+            .set_dictionary_size(300)
+            .set_dir(dir.clone())
+            .set_extension_to_read(String::from("txt"))
+            .set_worker_count(Some(1))
+            .set_disk_backed_file(Some(format!("{dir}/no_such_dir/units")))
+            .to_owned(),
+    );
+
+    assert!(result.is_err());
+
+    // but checkpoints and snapshots that cannot be written are only logged
+    let result = construct_dictionary_from_dir(
+        DictionaryConfig::default()
+            .set_dictionary_size(300)
+            .set_dir(dir.clone())
+            .set_extension_to_read(String::from("txt"))
+            .set_worker_count(Some(1))
+            .set_checkpoint_file(Some(format!("{dir}/no_such_dir/checkpoint")))
+            .set_checkpoint_every_merges(Some(10))
+            .set_snapshot_sizes(vec![280])
+            .set_snapshot_file(Some(format!("{dir}/no_such_dir/snapshot")))
+            .to_owned(),
+    );
+
+    assert!(result.is_ok());
+    crate::files::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn snapshot_test() {
+    // big enough to reach all the sizes before `minimum_appearance` stops it
+    let mut bytes = vec![];
+
+    for i in 0..2000 {
+        bytes.extend_from_slice(format!("{i}: the quick brown fox {} jumps over the lazy dog {}.\n", i * 7 % 101, i % 13).as_bytes());
+    }
+
+    let sizes = vec![300, 280, 320, 100_000];
+    let config = DictionaryConfig::default()
+        .set_dictionary_size(340)
+        .set_snapshot_sizes(sizes.clone())
+        .to_owned();
+
+    let (result, snapshots) = construct_dictionary_with_snapshots(&bytes, config.clone());
+
+    assert_eq!(
+        snapshots.iter().map(|(size, _)| *size).collect::<Vec<_>>(),
+        vec![280, 300, 320],
+    );
+
+    for (size, snapshot) in snapshots.iter() {
+        let standalone = construct_dictionary(
+            &bytes,
+            config.clone().set_dictionary_size(*size).set_snapshot_sizes(vec![]).to_owned(),
+        );
+
+        assert_eq!(snapshot.stop_reason(), Some(StopReason::DictionarySize));
+        assert!(*snapshot == standalone);
+        assert!(snapshot.merges().len() <= result.merges().len());
+        assert_eq!(snapshot.merges(), &result.merges()[..snapshot.merges().len()]);
+    }
+}
+
+#[test]
+fn trainer_test() {
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<Merge>>>);
+
+    impl Observer for Recorder {
+        fn on_merge(&mut self, merge: &Merge) {
+            self.0.lock().unwrap().push(merge.clone());
+        }
+    }
+
+    let bytes = sample_corpus();
+    let config = DictionaryConfig::default().set_dictionary_size(300).to_owned();
+    let recorded = Arc::new(Mutex::new(vec![]));
+    let mut trainer = Trainer::new(&bytes, config.clone());
+    trainer.add_observer(Box::new(Recorder(recorded.clone())));
+
+    let mut merges = vec![];
+
+    while let Some(merge) = trainer.next_merge() {
+        assert_eq!(merge.token, [merge.left.as_slice(), merge.right.as_slice()].concat());
+        merges.push(merge);
+    }
+
+    assert!(trainer.next_merge().is_none());
+    assert_eq!(merges, *recorded.lock().unwrap());
+
+    // counts never increase, and each merge makes the sequence shorter
+    for i in 1..merges.len() {
+        assert!(merges[i].count <= merges[i - 1].count);
+        assert!(merges[i].sequence_length < merges[i - 1].sequence_length);
+    }
+
+    assert!(trainer.finish() == construct_dictionary(&bytes, config.clone()));
+
+    // stopping early
+    let mut trainer = Trainer::new(&bytes, config);
+
+    for _ in 0..5 {
+        trainer.next_merge().unwrap();
+    }
+
+    let dictionary = trainer.finish();
+    assert_eq!(dictionary.merges(), &merges[..5].iter().map(|m| (m.left.clone(), m.right.clone())).collect::<Vec<_>>()[..]);
+}
+
+#[test]
+fn early_stopping_test() {
+    let bytes = sample_corpus();
+    let config = DictionaryConfig::default().set_dictionary_size(260).to_owned();
+
+    let result = construct_dictionary(&bytes, config.clone());
+    assert_eq!(result.stop_reason(), Some(StopReason::DictionarySize));
+
+    let result = construct_dictionary(&bytes, config.clone().set_time_limit(Some(Duration::ZERO)).to_owned());
+    assert_eq!(result.stop_reason(), Some(StopReason::TimeLimit));
+    assert_eq!(result.merges().len(), 1);
+
+    let result = construct_dictionary(&bytes, config.clone().set_memory_limit(Some(0)).to_owned());
+
+    if crate::utils::resident_memory().is_some() {
+        assert_eq!(result.stop_reason(), Some(StopReason::MemoryLimit));
+        assert_eq!(result.merges().len(), 1);
+    }
+
+    // the last merges only shrink the data a little
+    let mut trainer = Trainer::new(&bytes, config.clone().set_dictionary_size(100_000).set_minimum_gain(4, Some(0.01)).to_owned());
+    let mut lengths = vec![bytes.len()];
+
+    while let Some(merge) = trainer.next_merge() {
+        lengths.push(merge.sequence_length);
+    }
+
+    assert_eq!(trainer.stop_reason(), Some(StopReason::GainPlateau));
+
+    let n = lengths.len();
+    assert!(n > 5);
+    assert!(((lengths[n - 5] - lengths[n - 1]) as f64 / bytes.len() as f64) < 0.01);
+    assert!(((lengths[n - 6] - lengths[n - 2]) as f64 / bytes.len() as f64) >= 0.01);
+
+    // a window of 0 merges never stops it
+    let result = construct_dictionary(&bytes, config.clone().set_minimum_gain(0, Some(1.0)).to_owned());
+    assert_eq!(result.stop_reason(), Some(StopReason::DictionarySize));
+}
+
+#[test]
+fn run_count_test() {
+    let a = b'a' as Unit;
+    let b = b'b' as Unit;
+
+    for len in 0..9 {
+        let s = vec![a; len];
+        assert_eq!(count_pairs(&s).get(&into_pair(a, a)).copied().unwrap_or(0), len / 2);
+    }
+
+    let s = [a, a, a, b, a, a, b, b, b, b, b];
+    let counts = count_pairs(&s);
+    assert_eq!(counts.get(&into_pair(a, a)), Some(&2));
+    assert_eq!(counts.get(&into_pair(b, b)), Some(&2));
+    assert_eq!(counts.get(&into_pair(a, b)), Some(&2));
+    assert_eq!(counts.get(&into_pair(b, a)), Some(&1));
+
+    // every count has to be what the replacement actually does
+    let samples = [
+        b"aaaa    ====----" as &[u8],
+        b"== title ==\n\n------\n\n    code    code",
+        b"abababaaabbbaaaa",
+    ];
+
+    for sample in samples.into_iter() {
+        let s = bytes_to_units(sample);
+
+        for (pair, count) in count_pairs(&s).into_iter() {
+            let replaced = assign_pair_to_new_unit(&s, pair, 256);
+            assert_eq!(replaced.iter().filter(|unit| **unit == 256).count(), count);
+            assert_eq!(s.len() - replaced.len(), count);
+        }
+    }
+
+    // a long run of spaces shouldn't beat a pair that actually appears more
+    let mut bytes = b" ".repeat(60);
+    bytes.extend_from_slice(&b"xy".repeat(40));
+    let mut unit_map = default_unit_map();
+    let config = DictionaryConfig::default().set_minimum_appearance(Some(2)).to_owned();
+    let merged = step(&mut Units::from_bytes(&bytes), &mut unit_map, &mut UnitAllocator::new(), &config).unwrap();
+
+    assert_eq!(merged, Some((into_pair(b'x' as Unit, b'y' as Unit), 40)));
+}
+
+#[test]
+fn scoring_test() {
+    use std::sync::Arc;
+
+    // `ab` appears the most, but `a` and `b` appear everywhere, while `xy` always appear together
+    let mut bytes = vec![];
+
+    for i in 0..40 {
+        bytes.extend_from_slice(b"ab ac ad ba ca da ");
+
+        if i % 4 == 0 {
+            bytes.extend_from_slice(b"xyz ");
+        }
+    }
+
+    let first_merge = |scoring: Scoring| {
+        let mut unit_map = default_unit_map();
+        let config = DictionaryConfig::default().set_scoring(scoring).to_owned();
+        let merged = step(&mut Units::from_bytes(&bytes), &mut unit_map, &mut UnitAllocator::new(), &config).unwrap();
+        let (c1, c2) = from_pair(merged.unwrap().0);
+
+        [c1 as u8, c2 as u8]
+    };
+
+    assert_eq!(&first_merge(Scoring::Frequency), b"a ");
+    assert_eq!(&first_merge(Scoring::Likelihood), b"xy");
+    assert_eq!(&first_merge(Scoring::Pmi), b"xy");
+
+    struct LastByte;
+
+    impl MergeScorer for LastByte {
+        fn score(&self, candidate: &MergeCandidate) -> f64 {
+            candidate.right[0] as f64
+        }
+    }
+
+    assert_eq!(&first_merge(Scoring::Custom(Arc::new(LastByte))), b"yz");
+
+    // a longer token saves more bytes
+    let scoring = Scoring::LengthWeighted;
+    let short = MergeCandidate { left: b"a", right: b"b", count: 10, left_count: 0, right_count: 0, total: 0 };
+    let long = MergeCandidate { left: b"abc", right: b"de", count: 5, left_count: 0, right_count: 0, total: 0 };
+    assert!(scoring.score(&long) > scoring.score(&short));
+
+    // the other scorings also have to be deterministic
+    for scoring in [Scoring::Pmi, Scoring::Likelihood, Scoring::LengthWeighted] {
+        let config = DictionaryConfig::default().set_scoring(scoring).set_dictionary_size(300).to_owned();
+        let result = construct_dictionary(&sample_corpus(), config.clone());
+
+        assert!(!result.merges().is_empty());
+        assert!(result == construct_dictionary(&sample_corpus(), config));
+    }
+}
+
+#[test]
+fn forced_and_forbidden_tokens_test() {
+    let mut bytes = sample_corpus();
+    bytes.extend_from_slice("fn main() { return 가나; } ".repeat(8).as_bytes());
+
+    let forced = vec![b"fn main()".to_vec(), "가나".as_bytes().to_vec(), b"zebra".to_vec(), b"q".to_vec()];
+    let config = DictionaryConfig::default()
+        .set_dictionary_size(300)
+        .set_keep_single_byte_tokens(false)
+        .set_forced_tokens(forced.clone())
+        .set_forbidden_tokens(vec![b"lazy".to_vec(), b"ox".to_vec()])
+        .to_owned();
+    let result = construct_dictionary(&bytes, config.clone());
+
+    assert!(result.len() <= 300);
+
+    // even the ones that are not in the input
+    for token in forced.iter() {
+        assert!(result.get(token).is_some(), "{:?}", String::from_utf8_lossy(token));
+    }
+
+    for (token, _) in result.iter() {
+        assert!(!token.windows(4).any(|w| w == b"lazy"));
+        assert!(!token.windows(2).any(|w| w == b"ox"));
+    }
+
+    // the forced tokens are really used (they may be merged further)
+    assert_eq!(
+        result.iter().map(
+            |(token, n)| token.windows(9).filter(|w| *w == b"fn main()").count() * n
+        ).sum::<usize>(),
+        8,
+    );
+
+    // it works with `character_coverage`, too
+    let result = construct_dictionary(&bytes, config.clone().set_character_coverage(Some(1.0)).to_owned());
+    assert!(result.get("가나".as_bytes()).is_some());
+    assert!(result.get(b"zebra".as_slice()).is_some());
+
+    let mut encoder = result.encoder();
+    assert_eq!(encoder.encode("가나".as_bytes()), vec!["가나".as_bytes().to_vec()]);
+
+    // no token has a digit or ends with a space
+    let patterns = vec![regex::bytes::Regex::new(r"[0-9]").unwrap(), regex::bytes::Regex::new(r"\S $").unwrap()];
+    let result = construct_dictionary(&bytes, config.clone().set_forbidden_patterns(patterns.clone()).to_owned());
+
+    for (w1, w2) in result.merges().iter() {
+        let token = [w1.as_slice(), w2.as_slice()].concat();
+
+        if !forced.iter().any(|forced| forced.starts_with(&token)) {
+            assert!(patterns.iter().all(|pattern| !pattern.is_match(&token)), "{:?}", String::from_utf8_lossy(&token));
+        }
+    }
+
+    // forced tokens win over forbidden ones, and so do their prefixes
+    let result = construct_dictionary(
+        &bytes,
+        config.clone().set_forbidden_tokens(vec![b"ma".to_vec()]).to_owned(),
+    );
+
+    assert!(result.merges().contains(&(b"fn m".to_vec(), b"a".to_vec())));
+    assert!(result.get(b"fn main()".as_slice()).is_some());
+}
+
+#[test]
+fn parallel_test() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    let mut rng = StdRng::seed_from_u64(0);
+
+    // small alphabet, so that there are many runs
+    let s = (0..10_000).map(|_| rng.gen_range(0..3) as Unit).collect::<Vec<_>>();
+
+    for threads in [1, 2, 3, 7, 64] {
+        assert_eq!(count_pairs_parallel(&s, threads), count_pairs(&s));
+
+        for (pair, _) in count_pairs(&s).into_iter() {
+            assert_eq!(
+                assign_pair_to_new_unit_parallel(&s, pair, 256, threads),
+                assign_pair_to_new_unit(&s, pair, 256),
+            );
+        }
+    }
+
+    let runs = vec![1; 1001];
+    assert_eq!(count_pairs_parallel(&runs, 8), count_pairs(&runs));
+    assert_eq!(
+        assign_pair_to_new_unit_parallel(&runs, into_pair(1, 1), 256, 8),
+        assign_pair_to_new_unit(&runs, into_pair(1, 1), 256),
+    );
+
+    let bytes = sample_corpus().repeat(28);
+    let config = DictionaryConfig::default().set_dictionary_size(300).to_owned();
+    assert!(threads_for(bytes.len(), Some(4)) > 1);
+    assert!(
+        construct_dictionary(&bytes, config.clone().set_thread_count(Some(4)).to_owned())
+        == construct_dictionary(&bytes, config)
+    );
+}
+
+#[test]
+fn unit_allocator_test() {
+    let mut unit_map = default_unit_map();
+    let mut allocator = UnitAllocator::new();
+    let mut units = Units::from_bytes(&b"abcabcabc".repeat(4));
+    let config = DictionaryConfig::default().set_minimum_appearance(Some(2)).to_owned();
+
+    step(&mut units, &mut unit_map, &mut allocator, &config).unwrap().unwrap();
+    step(&mut units, &mut unit_map, &mut allocator, &config).unwrap().unwrap();
+    assert!(unit_map.contains_key(&256) && unit_map.contains_key(&257));
+
+    // `ab` is not used anymore, but its unit is not reused
+    assert_eq!(remove_unnecessary_units_in_map(units.iter(), &mut unit_map, true, &HashSet::new()), 1);
+    step(&mut units, &mut unit_map, &mut allocator, &config).unwrap().unwrap();
+    assert!(!unit_map.contains_key(&256));
+    assert!(unit_map.contains_key(&258));
+
+    assert_eq!(UnitAllocator::after(&unit_map).allocate(), 259);
+}
+
+#[test]
+fn units_test() {
+    let mut units = Units::from_bytes(b"abcabcab");
+    assert!(matches!(units, Units::Narrow(_)));
+
+    units.replace_pair(into_pair(b'a' as Unit, b'b' as Unit), 300, 1).unwrap();
+    assert_eq!(units.iter().collect::<Vec<_>>(), vec![300, b'c' as Unit, 300, b'c' as Unit, 300]);
+    assert!(matches!(units, Units::Narrow(_)));
+
+    // it widens when a unit doesn't fit in `u16`
+    units.replace_pair(into_pair(300, b'c' as Unit), 70_000, 1).unwrap();
+    assert_eq!(units.iter().collect::<Vec<_>>(), vec![70_000, 70_000, 300]);
+    assert!(matches!(units, Units::Wide(_)));
+
+    let mut visited = vec![];
+    units.for_each(|unit| { visited.push(unit); });
+    assert_eq!(visited, vec![70_000, 70_000, 300]);
+    assert_eq!(units.counts(), vec![(70_000, 2), (300, 1)].into_iter().collect());
+
+    assert!(matches!(Units::from_units(vec![1, 2, 3]), Units::Narrow(_)));
+    assert!(matches!(Units::from_units(vec![1, 70_000]), Units::Wide(_)));
+
+    let mut pushed = Units::Narrow(vec![]);
+    pushed.push(1);
+    assert!(matches!(pushed, Units::Narrow(_)));
+    pushed.push(70_000);
+    assert_eq!(pushed, Units::from_units(vec![1, 70_000]));
+    assert!(matches!(pushed, Units::Wide(_)));
+
+    // the same result, whichever the storage is
+    let bytes = sample_corpus();
+    let config = DictionaryConfig::default().set_dictionary_size(300).to_owned();
+    let mut narrow = Units::from_bytes(&bytes);
+    let mut wide = Units::Wide(bytes.iter().map(|b| *b as Unit).collect());
+    let mut narrow_map = default_unit_map();
+    let mut wide_map = default_unit_map();
+    let mut narrow_allocator = UnitAllocator::new();
+    let mut wide_allocator = UnitAllocator::new();
+
+    for _ in 0..20 {
+        assert_eq!(
+            step(&mut narrow, &mut narrow_map, &mut narrow_allocator, &config).unwrap(),
+            step(&mut wide, &mut wide_map, &mut wide_allocator, &config).unwrap(),
+        );
+        assert_eq!(narrow, wide);
+    }
+}
+
+#[test]
+fn disk_backed_test() {
+    let mut bytes = sample_corpus();
+    bytes.extend_from_slice("가나다 가나다\n  라마 가나다\n".repeat(16).as_bytes());
+
+    let files = (0..3).map(
+        |i| temp_path(&format!("bpe_rs_disk_backed_test_{i}.txt"))
+    ).collect::<Vec<_>>();
+    let working_file = temp_path("bpe_rs_disk_backed_test.units");
+    let third = bytes.len() / 3;
+
+    for (i, file) in files.iter().enumerate() {
+        let end = if i == 2 { bytes.len() } else { (i + 1) * third };
+        crate::files::write_bytes(file, &bytes[(i * third)..end], WriteMode::CreateOrTruncate).unwrap();
+    }
+
+    for config in [
+        DictionaryConfig::default(),
+        DictionaryConfig::default()
+            .set_character_coverage(Some(0.9))
+            .set_normalizers(vec![Normalizer::Nfkc, Normalizer::WhitespaceMarker])
+            .to_owned(),
+        DictionaryConfig::default()
+            .set_normalizers(vec![Normalizer::CollapseWhitespace])
+            .to_owned(),
+    ] {
+        let config = config.clone().set_dictionary_size(300).to_owned();
+        let disk_backed = construct_dictionary_from_files(
+            &files,
+            config.clone().set_disk_backed_file(Some(working_file.clone())).to_owned(),
+        ).unwrap();
+
+        assert!(disk_backed == construct_dictionary(&bytes, config.clone()));
+        assert!(construct_dictionary_from_files(&files, config).unwrap() == disk_backed);
+
+        // the working file is removed
+        assert!(!exists(&working_file));
+    }
+
+    // blocks end at newlines, and the normalizers are applied the same way
+    let config = DictionaryConfig::default()
+        .set_normalizers(vec![Normalizer::Nfkc, Normalizer::Lowercase, Normalizer::CollapseWhitespace, Normalizer::WhitespaceMarker])
+        .to_owned();
+
+    for block_size in [1, 7, 64, 1 << 20] {
+        let mut result = vec![];
+        for_each_block(&files, &config, block_size, |block| { result.extend_from_slice(block); }).unwrap();
+
+        assert_eq!(result, normalize(&bytes, &config.normalizers).bytes);
+    }
+
+    // even across files, with a separator
+    let mut config = config.clone();
+    config.dir_option.file_separator = Some(b'\n');
+    let mut result = vec![];
+    for_each_block(&files, &config, 7, |block| { result.extend_from_slice(block); }).unwrap();
+
+    let joined = files.iter().map(|file| read_bytes(file).unwrap()).collect::<Vec<_>>().join(&b'\n');
+    assert_eq!(result, normalize(&joined, &config.normalizers).bytes);
+
+    for file in files.iter() {
+        remove_file(file).unwrap();
+    }
+}
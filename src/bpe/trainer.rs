@@ -0,0 +1,470 @@
+use super::{
+    MINIMUN_STRING_LENGTH,
+    MappedUnits,
+    Units,
+    from_pair,
+    initial_state,
+    initial_state_from_files,
+    remove_unnecessary_units_in_map,
+    step,
+};
+use crate::checkpoint::Checkpoint;
+use crate::dictionary::{Dictionary, DictionaryConfig};
+use crate::files::{FileError, exists};
+use crate::log::write_log;
+use crate::normalizer::normalize;
+use crate::utils::resident_memory;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// `memory_limit` is checked at most once in this interval
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Why the training stopped.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum StopReason {
+    /// It reached `dictionary_size`.
+    DictionarySize,
+
+    /// No pair appears `minimum_appearance` times.
+    MinimumAppearance,
+
+    /// The data got shorter than `MINIMUN_STRING_LENGTH`.
+    TooShort,
+
+    /// `minimum_gain`
+    GainPlateau,
+
+    /// `time_limit`
+    TimeLimit,
+
+    /// `memory_limit`
+    MemoryLimit,
+
+    /// Writing `disk_backed_at` failed. See `Trainer::error`.
+    FileError,
+}
+
+/// A merge chosen by `Trainer::next_merge`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Merge {
+    pub left: Vec<u8>,
+    pub right: Vec<u8>,
+
+    // `left` + `right`
+    pub token: Vec<u8>,
+
+    /// How many times the pair appeared before the merge.
+    pub count: usize,
+
+    /// Length of the training data (in units) after the merge.
+    pub sequence_length: usize,
+
+    /// Number of tokens the trainer has after the merge, including the ones that will be removed at the end.
+    pub vocabulary_size: usize,
+}
+
+/// Callbacks of `Trainer`. Every method does nothing by default.
+pub trait Observer {
+    fn on_merge(&mut self, _merge: &Merge) {}
+
+    fn on_snapshot(&mut self, _size: usize, _dictionary: &Dictionary) {}
+
+    fn on_checkpoint(&mut self, _path: &str, _merges: usize) {}
+
+    fn on_finish(&mut self, _dictionary: &Dictionary) {}
+}
+
+/// BPE training, one merge at a time. It ignores `config.model`.
+///
+/// ```nohighlight
+/// let mut trainer = Trainer::new(bytes, config);
+///
+/// while let Some(merge) = trainer.next_merge() {
+///     // your own stopping rule
+///     if merge.count < 100 { break; }
+/// }
+///
+/// let dictionary = trainer.finish();
+/// ```
+///
+/// It stops by itself when it reaches `dictionary_size`, there's nothing to merge or one of
+/// `minimum_gain`, `time_limit` and `memory_limit` is hit. See `Trainer::stop_reason`.
+/// `construct_dictionary` is the same as calling `next_merge` until it returns `None`.
+pub struct Trainer {
+    config: DictionaryConfig,
+    state: Checkpoint,
+    observers: Vec<Box<dyn Observer>>,
+    stop_reason: Option<StopReason>,
+    error: Option<FileError>,
+    started_at: Instant,
+
+    // lengths of the data after the last `gain_window` merges
+    recent_lengths: VecDeque<usize>,
+
+    last_checkpoint: Instant,
+    merges_since_checkpoint: usize,
+
+    // `None` before the first check
+    last_memory_check: Option<Instant>,
+
+    // in descending order, so that the smallest one can be popped
+    snapshot_sizes: Vec<usize>,
+    snapshots: Vec<(usize, Dictionary)>,
+}
+
+// `None` if it doesn't resume. If the checkpoint cannot be loaded, it starts over.
+fn resumed_state(config: &DictionaryConfig) -> Option<Checkpoint> {
+    match &config.checkpoint_at {
+        Some(path) if config.resume_from_checkpoint && exists(path) => match Checkpoint::load(path, config.disk_backed_at.as_deref()) {
+            Ok(state) => {
+                write_log(
+                    config.write_log_at.clone(),
+                    "trainer",
+                    &format!("resumed from checkpoint {path}"),
+                );
+
+                Some(state)
+            },
+            Err(e) => {
+                write_log(
+                    config.write_log_at.clone(),
+                    "trainer",
+                    &format!("failed to load checkpoint {path}, so it starts over: {e}"),
+                );
+
+                None
+            },
+        },
+        _ => None,
+    }
+}
+
+// it moves the units to `disk_backed_at`, if they're not there yet
+fn map_units(state: &mut Checkpoint, config: &DictionaryConfig) -> Result<(), FileError> {
+    if let Some(path) = &config.disk_backed_at {
+        if !matches!(state.units, Units::Mapped(_)) {
+            state.units = Units::Mapped(MappedUnits::write(path, state.units.iter())?);
+        }
+    }
+
+    Ok(())
+}
+
+impl Trainer {
+    /// It applies the normalizers of `config`, and resumes from `checkpoint_at` if `resume_from_checkpoint` is set.
+    pub fn new(bytes: &[u8], config: DictionaryConfig) -> Self {
+        let normalized;
+        let bytes = if config.normalizers.is_empty() {
+            bytes
+        } else {
+            normalized = normalize(bytes, &config.normalizers).bytes;
+            &normalized
+        };
+
+        let (mut state, resumed) = match resumed_state(&config) {
+            Some(state) => (state, true),
+            None => (initial_state(bytes, &config), false),
+        };
+
+        if let Err(e) = map_units(&mut state, &config) {
+            write_log(
+                config.write_log_at.clone(),
+                "trainer",
+                &format!("failed to write the units to disk, so they're kept in memory: {e}"),
+            );
+        }
+
+        Trainer::with_state(state, config, resumed)
+    }
+
+    /// Same as `Trainer::new` with the concatenation of `files`, but the files are read block by block,
+    /// so they never have to be in memory at once. With `disk_backed_at`, neither does the training data.\
+    /// The normalizers are applied to each block, which ends at a newline (the whitespaces before it go to the next block).
+    pub fn from_files(files: &[String], config: DictionaryConfig) -> Result<Self, FileError> {
+        let (mut state, resumed) = match resumed_state(&config) {
+            Some(state) => (state, true),
+            None => (initial_state_from_files(files, &config)?, false),
+        };
+
+        map_units(&mut state, &config)?;
+        Ok(Trainer::with_state(state, config, resumed))
+    }
+
+    fn with_state(state: Checkpoint, config: DictionaryConfig, resumed: bool) -> Self {
+        // the state at the sizes that a checkpoint has already passed is gone
+        let mut snapshot_sizes = config.snapshot_sizes.iter().filter(
+            |size| **size < config.dictionary_size && (!resumed || **size > state.unit_map.len())
+        ).copied().collect::<Vec<_>>();
+        snapshot_sizes.sort();
+        snapshot_sizes.dedup();
+        snapshot_sizes.reverse();
+
+        let recent_lengths = VecDeque::from([state.units.len()]);
+
+        Trainer {
+            config,
+            state,
+            observers: vec![],
+            stop_reason: None,
+            error: None,
+            started_at: Instant::now(),
+            recent_lengths,
+            last_checkpoint: Instant::now(),
+            merges_since_checkpoint: 0,
+            last_memory_check: None,
+            snapshot_sizes,
+            snapshots: vec![],
+        }
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) -> &mut Self {
+        self.observers.push(observer);
+
+        self
+    }
+
+    /// It returns `None` if the training is over. Once it returns `None`, it always returns `None`.
+    pub fn next_merge(&mut self) -> Option<Merge> {
+        if self.stop_reason.is_some() {
+            return None;
+        }
+
+        let merged_pair = step(&mut self.state.units, &mut self.state.unit_map, &mut self.state.allocator, &self.config);
+
+        let merge = match merged_pair {
+            Ok(Some((pair, count))) => {
+                let (c1, c2) = from_pair(pair);
+                let left = self.state.unit_map.get(&c1).unwrap().to_vec();
+                let right = self.state.unit_map.get(&c2).unwrap().to_vec();
+                self.state.merges.push((left.clone(), right.clone()));
+                self.merges_since_checkpoint += 1;
+
+                Merge {
+                    token: [left.as_slice(), right.as_slice()].concat(),
+                    left,
+                    right,
+                    count,
+                    sequence_length: self.state.units.len(),
+                    vocabulary_size: self.state.unit_map.len(),
+                }
+            },
+            Ok(None) => {
+                self.stop(StopReason::MinimumAppearance);
+                return None;
+            },
+            Err(e) => {
+                self.error = Some(e);
+                self.stop(StopReason::FileError);
+                return None;
+            },
+        };
+
+        for observer in self.observers.iter_mut() {
+            observer.on_merge(&merge);
+        }
+
+        if self.state.units.len() <= MINIMUN_STRING_LENGTH {
+            self.stop(StopReason::TooShort);
+            return Some(merge);
+        }
+
+        self.take_snapshots();
+
+        if self.state.unit_map.len() >= self.config.dictionary_size {
+            self.remove_unnecessary_units();
+
+            if self.state.unit_map.len() >= self.config.dictionary_size {
+                self.stop(StopReason::DictionarySize);
+                return Some(merge);
+            }
+        }
+
+        if let Some(reason) = self.early_stop_reason() {
+            self.stop(reason);
+            return Some(merge);
+        }
+
+        self.save_checkpoint_if_needed();
+        Some(merge)
+    }
+
+    /// `None` if the training is not over.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// Why it stopped with `StopReason::FileError`. The units may be broken, so don't `finish` it.
+    pub fn error(&self) -> Option<&FileError> {
+        self.error.as_ref()
+    }
+
+    /// It can be called before `next_merge` returns `None`: the result is what the trainer has so far.
+    pub fn finish(self) -> Dictionary {
+        self.finish_with_snapshots().0
+    }
+
+    /// Snapshots at `snapshot_sizes`, in ascending order. If the training ended before a size, its snapshot is the final dictionary.
+    pub fn finish_with_snapshots(mut self) -> (Dictionary, Vec<(usize, Dictionary)>) {
+        let counts = self.state.units.counts();
+        remove_unnecessary_units_in_map(counts.keys().copied(), &mut self.state.unit_map, self.config.keep_single_byte_tokens, &self.state.protected);
+
+        let mut result = Dictionary::from_unit_counts(&counts, &self.state.unit_map, self.state.merges);
+        result.set_normalizers(self.config.normalizers.clone());
+        result.set_stop_reason(self.stop_reason);
+
+        if let Some(initial_dictionary) = &self.config.initial_dictionary {
+            result.keep_ids_of(initial_dictionary);
+        }
+
+        for size in self.snapshot_sizes.into_iter().rev() {
+            self.snapshots.push((size, result.clone()));
+        }
+
+        for observer in self.observers.iter_mut() {
+            observer.on_finish(&result);
+        }
+
+        (result, self.snapshots)
+    }
+
+    /// Merges so far, in the order they were merged.
+    pub fn merges(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.state.merges
+    }
+
+    /// Length of the training data in units.
+    pub fn sequence_length(&self) -> usize {
+        self.state.units.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stop_reason.is_some()
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.stop_reason = Some(reason);
+
+        write_log(
+            self.config.write_log_at.clone(),
+            "trainer",
+            &format!("stopped after {} merges: {reason:?}", self.state.merges.len()),
+        );
+    }
+
+    fn early_stop_reason(&mut self) -> Option<StopReason> {
+        // a window of 0 merges has no gain to measure
+        if let Some(minimum) = self.config.minimum_gain.filter(|_| self.config.gain_window > 0) {
+            self.recent_lengths.push_back(self.state.units.len());
+
+            if self.recent_lengths.len() > self.config.gain_window + 1 {
+                self.recent_lengths.pop_front();
+            }
+
+            if self.recent_lengths.len() == self.config.gain_window + 1 {
+                let before = *self.recent_lengths.front().unwrap();
+                let gain = (before - self.state.units.len()) as f64 / self.state.input_length.max(1) as f64;
+
+                if gain < minimum {
+                    return Some(StopReason::GainPlateau);
+                }
+            }
+        }
+
+        if let Some(limit) = self.config.time_limit {
+            if self.started_at.elapsed() >= limit {
+                return Some(StopReason::TimeLimit);
+            }
+        }
+
+        // it reads `/proc`, which is too slow to do at every merge
+        if let Some(limit) = self.config.memory_limit {
+            if self.last_memory_check.map(|t| t.elapsed() >= MEMORY_CHECK_INTERVAL).unwrap_or(true) {
+                self.last_memory_check = Some(Instant::now());
+
+                if resident_memory().map(|memory| memory > limit).unwrap_or(false) {
+                    return Some(StopReason::MemoryLimit);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn remove_unnecessary_units(&mut self) {
+        let counts = self.state.units.counts();
+        remove_unnecessary_units_in_map(counts.keys().copied(), &mut self.state.unit_map, self.config.keep_single_byte_tokens, &self.state.protected);
+    }
+
+    // it does what `next_merge` does when it reaches `dictionary_size`, but with a copy of `unit_map`
+    fn take_snapshots(&mut self) {
+        while let Some(size) = self.snapshot_sizes.last().copied() {
+            if self.state.unit_map.len() < size {
+                break;
+            }
+
+            let counts = self.state.units.counts();
+            let mut unit_map = self.state.unit_map.clone();
+            remove_unnecessary_units_in_map(counts.keys().copied(), &mut unit_map, self.config.keep_single_byte_tokens, &self.state.protected);
+
+            if unit_map.len() < size {
+                break;
+            }
+
+            // a run whose `dictionary_size` is `size` stops here, for this reason
+            let mut snapshot = Dictionary::from_unit_counts(&counts, &unit_map, self.state.merges.clone());
+            snapshot.set_normalizers(self.config.normalizers.clone());
+            snapshot.set_stop_reason(Some(StopReason::DictionarySize));
+
+            if let Some(initial_dictionary) = &self.config.initial_dictionary {
+                snapshot.keep_ids_of(initial_dictionary);
+            }
+
+            for observer in self.observers.iter_mut() {
+                observer.on_snapshot(size, &snapshot);
+            }
+
+            self.snapshots.push((size, snapshot));
+            self.snapshot_sizes.pop();
+
+            write_log(
+                self.config.write_log_at.clone(),
+                "trainer",
+                &format!("took a snapshot of size {size}"),
+            );
+        }
+    }
+
+    fn save_checkpoint_if_needed(&mut self) {
+        if let Some(path) = &self.config.checkpoint_at {
+            let by_merges = self.config.checkpoint_every_merges.map(|n| self.merges_since_checkpoint >= n).unwrap_or(false);
+            let by_time = self.config.checkpoint_interval.map(|t| self.last_checkpoint.elapsed() >= t).unwrap_or(false);
+
+            if by_merges || by_time {
+                // it tries again at the next interval
+                self.last_checkpoint = Instant::now();
+                self.merges_since_checkpoint = 0;
+
+                if let Err(e) = self.state.save(path) {
+                    write_log(
+                        self.config.write_log_at.clone(),
+                        "trainer",
+                        &format!("failed to save checkpoint at {path}: {e}"),
+                    );
+
+                    return;
+                }
+
+                for observer in self.observers.iter_mut() {
+                    observer.on_checkpoint(path, self.state.merges.len());
+                }
+
+                write_log(
+                    self.config.write_log_at.clone(),
+                    "trainer",
+                    &format!("saved checkpoint at {path} ({} merges)", self.state.merges.len()),
+                );
+            }
+        }
+    }
+}
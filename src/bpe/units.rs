@@ -0,0 +1,148 @@
+use super::{MappedUnits, Pair, Unit, count_pairs_parallel, replace_pair_in_place_parallel};
+use crate::files::FileError;
+use std::collections::HashMap;
+
+/// A type that can store `Unit`s. `u16` can only store the units smaller than 65536.
+pub trait UnitLike: Copy + Eq + Send + Sync {
+    fn to_unit(self) -> Unit;
+
+    fn from_unit(unit: Unit) -> Self;
+}
+
+impl UnitLike for u16 {
+    fn to_unit(self) -> Unit {
+        self as Unit
+    }
+
+    fn from_unit(unit: Unit) -> Self {
+        unit as u16
+    }
+}
+
+impl UnitLike for u32 {
+    fn to_unit(self) -> Unit {
+        self
+    }
+
+    fn from_unit(unit: Unit) -> Self {
+        unit
+    }
+}
+
+/// The training data. It uses `u16` while every unit fits in it, and widens to `u32` when a new unit doesn't.
+/// A merge is applied in place, so the buffer is reused.
+#[derive(Debug)]
+pub enum Units {
+    Narrow(Vec<u16>),
+    Wide(Vec<u32>),
+
+    /// `DictionaryConfig::disk_backed_at`
+    Mapped(MappedUnits),
+}
+
+impl Units {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Units::Narrow(bytes.iter().map(|byte| *byte as u16).collect())
+    }
+
+    pub fn from_units(units: Vec<Unit>) -> Self {
+        if units.iter().all(|unit| *unit <= u16::MAX as Unit) {
+            Units::Narrow(units.into_iter().map(u16::from_unit).collect())
+        }
+
+        else {
+            Units::Wide(units)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Units::Narrow(units) => units.len(),
+            Units::Wide(units) => units.len(),
+            Units::Mapped(units) => units.len(),
+        }
+    }
+
+    /// It calls `f` with each unit, in order. Unlike `iter`, the loop is specialized for each variant,
+    /// so use this one for passes over the whole data.
+    pub fn for_each<F: FnMut(Unit)>(&self, mut f: F) {
+        match self {
+            Units::Narrow(units) => units.iter().for_each(|unit| f(unit.to_unit())),
+            Units::Wide(units) => units.iter().for_each(|unit| f(*unit)),
+            Units::Mapped(units) => units.as_slice().iter().for_each(|unit| f(*unit)),
+        }
+    }
+
+    /// how many times each unit appears
+    pub fn counts(&self) -> HashMap<Unit, usize> {
+        let mut result = HashMap::new();
+        self.for_each(|unit| { *result.entry(unit).or_insert(0) += 1; });
+
+        result
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Unit> + '_> {
+        match self {
+            Units::Narrow(units) => Box::new(units.iter().map(|unit| unit.to_unit())),
+            Units::Wide(units) => Box::new(units.iter().copied()),
+            Units::Mapped(units) => Box::new(units.as_slice().iter().copied()),
+        }
+    }
+
+    pub fn count_pairs(&self, threads: usize) -> HashMap<Pair, usize> {
+        match self {
+            Units::Narrow(units) => count_pairs_parallel(units, threads),
+            Units::Wide(units) => count_pairs_parallel(units, threads),
+            Units::Mapped(units) => count_pairs_parallel(units.as_slice(), threads),
+        }
+    }
+
+    /// It widens `Narrow` if `unit` doesn't fit in `u16`.\
+    /// `Mapped` cannot grow: use `MappedUnits::create` instead.
+    pub fn push(&mut self, unit: Unit) {
+        if let Units::Narrow(units) = self {
+            if unit > u16::MAX as Unit {
+                *self = Units::Wide(units.iter().map(|unit| unit.to_unit()).collect());
+            }
+        }
+
+        match self {
+            Units::Narrow(units) => units.push(u16::from_unit(unit)),
+            Units::Wide(units) => units.push(unit),
+            Units::Mapped(_) => panic!("`Units::Mapped` cannot grow"),
+        }
+    }
+
+    /// `assign_pair_to_new_unit`, but in place. Only `Mapped` can fail, when it gives the disk space back.
+    pub fn replace_pair(&mut self, pair: Pair, new_unit: Unit, threads: usize) -> Result<(), FileError> {
+        if let Units::Narrow(units) = self {
+            if new_unit > u16::MAX as Unit {
+                *self = Units::Wide(units.iter().map(|unit| unit.to_unit()).collect());
+            }
+        }
+
+        match self {
+            Units::Narrow(units) => {
+                let len = replace_pair_in_place_parallel(units, pair, new_unit, threads);
+                units.truncate(len);
+            },
+            Units::Wide(units) => {
+                let len = replace_pair_in_place_parallel(units, pair, new_unit, threads);
+                units.truncate(len);
+            },
+            Units::Mapped(units) => {
+                let len = replace_pair_in_place_parallel(units.as_mut_slice(), pair, new_unit, threads);
+                units.truncate(len)?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+// `Narrow` and `Wide` are the same if they have the same units
+impl PartialEq for Units {
+    fn eq(&self, other: &Units) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
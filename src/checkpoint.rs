@@ -0,0 +1,228 @@
+use crate::bpe::{MappedUnits, Unit, UnitAllocator, UnitMapInternal, Units};
+use crate::files::{FileError, rename};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+#[cfg(test)]
+mod tests;
+
+pub const MAGIC: &[u8] = b"bpe-rs checkpoint\n";
+
+/// In-progress state of `construct_dictionary`.\
+/// Everything is little-endian: lengths are `u64` and units are `u32`.
+#[derive(Debug, PartialEq)]
+pub struct Checkpoint {
+    // in the order they were merged
+    pub merges: Vec<(Vec<u8>, Vec<u8>)>,
+    pub unit_map: UnitMapInternal,
+    pub units: Units,
+
+    // units of `initial_dictionary`
+    pub protected: HashSet<Unit>,
+
+    // units are never reused, so this has to be saved too
+    pub allocator: UnitAllocator,
+
+    // length of the input in bytes (after normalization), which `minimum_gain` is a ratio to
+    pub input_length: usize,
+}
+
+impl Checkpoint {
+    /// It writes to a temporary file first, so that a crash doesn't corrupt the previous checkpoint.\
+    /// The units are streamed to the file, so they're never copied in memory.
+    pub fn save(&self, path: &str) -> Result<(), FileError> {
+        let tmp_path = format!("{path}.tmp");
+        let file = File::create(&tmp_path).map_err(|e| FileError::from_std(e, &tmp_path))?;
+        let mut writer = BufWriter::new(file);
+
+        self.write(&mut writer).and_then(|_| writer.flush()).map_err(|e| FileError::from_std(e, &tmp_path))?;
+        drop(writer);
+
+        rename(&tmp_path, path)
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_len(w, self.merges.len())?;
+
+        for (w1, w2) in self.merges.iter() {
+            write_with_len(w, w1)?;
+            write_with_len(w, w2)?;
+        }
+
+        // the order has to be deterministic
+        let mut unit_map = self.unit_map.iter().collect::<Vec<_>>();
+        unit_map.sort();
+        write_len(w, unit_map.len())?;
+
+        for (unit, unit_bytes) in unit_map.into_iter() {
+            w.write_all(&unit.to_le_bytes())?;
+            write_with_len(w, unit_bytes)?;
+        }
+
+        let mut protected = self.protected.iter().collect::<Vec<_>>();
+        protected.sort();
+        write_len(w, protected.len())?;
+
+        for unit in protected.into_iter() {
+            w.write_all(&unit.to_le_bytes())?;
+        }
+
+        write_len(w, self.units.len())?;
+
+        // it's always `u32`, even if `units` is `Units::Narrow`
+        let mut result = Ok(());
+
+        self.units.for_each(|unit| {
+            if result.is_ok() {
+                result = w.write_all(&unit.to_le_bytes());
+            }
+        });
+
+        result?;
+        w.write_all(&self.allocator.peek().to_le_bytes())?;
+        write_len(w, self.input_length)
+    }
+
+    /// With `disk_backed_at`, the units are copied to the file (`MappedUnits`) instead of being loaded in memory.
+    pub fn load(path: &str, disk_backed_at: Option<&str>) -> Result<Self, FileError> {
+        let file = File::open(path).map_err(|e| FileError::from_std(e, path))?;
+        let mut reader = Reader { reader: BufReader::new(file), path };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(reader.invalid_file());
+        }
+
+        let mut merges = vec![];
+
+        for _ in 0..reader.len()? {
+            let w1 = reader.bytes()?;
+            let w2 = reader.bytes()?;
+            merges.push((w1, w2));
+        }
+
+        let mut unit_map = UnitMapInternal::new();
+
+        for _ in 0..reader.len()? {
+            let unit = reader.unit()?;
+            let unit_bytes = reader.bytes()?;
+            unit_map.insert(unit, unit_bytes.into());
+        }
+
+        let mut protected = HashSet::new();
+
+        for _ in 0..reader.len()? {
+            protected.insert(reader.unit()?);
+        }
+
+        let units_len = reader.len()?;
+        let units = match disk_backed_at {
+            Some(disk_backed_at) => {
+                let mut writer = MappedUnits::create(disk_backed_at)?;
+
+                for _ in 0..units_len {
+                    writer.push(reader.unit()?)?;
+                }
+
+                Units::Mapped(writer.finish()?)
+            },
+            None => {
+                // the length may be broken, so it's not trusted too much
+                let mut units = Vec::with_capacity(units_len.min(1 << 20));
+
+                for _ in 0..units_len {
+                    units.push(reader.unit()?);
+                }
+
+                Units::from_units(units)
+            },
+        };
+
+        // older checkpoints don't have it
+        let allocator = match reader.optional_unit()? {
+            Some(next) => UnitAllocator::starting_at(next),
+            None => UnitAllocator::after(&unit_map),
+        };
+
+        // older checkpoints don't have it either
+        let input_length = match reader.optional_len()? {
+            Some(input_length) => input_length,
+            None => units.len(),
+        };
+
+        Ok(Checkpoint { merges, unit_map, units, protected, allocator, input_length })
+    }
+}
+
+fn write_len<W: Write>(w: &mut W, len: usize) -> io::Result<()> {
+    w.write_all(&(len as u64).to_le_bytes())
+}
+
+fn write_with_len<W: Write>(w: &mut W, b: &[u8]) -> io::Result<()> {
+    write_len(w, b.len())?;
+    w.write_all(b)
+}
+
+struct Reader<'a, R: Read> {
+    reader: R,
+
+    // for the error messages
+    path: &'a str,
+}
+
+impl<R: Read> Reader<'_, R> {
+    fn invalid_file(&self) -> FileError {
+        FileError::unknown(
+            String::from("invalid checkpoint file"),
+            Some(self.path.to_string()),
+        )
+    }
+
+    // `None` if the file ends right here
+    fn optional<const N: usize>(&mut self) -> Result<Option<[u8; N]>, FileError> {
+        let mut result = [0; N];
+
+        match self.reader.read_exact(&mut result) {
+            Ok(()) => Ok(Some(result)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(FileError::from_std(e, self.path)),
+        }
+    }
+
+    fn exact<const N: usize>(&mut self) -> Result<[u8; N], FileError> {
+        self.optional()?.ok_or_else(|| self.invalid_file())
+    }
+
+    fn take(&mut self, n: usize) -> Result<Vec<u8>, FileError> {
+        let mut result = vec![];
+        (&mut self.reader).take(n as u64).read_to_end(&mut result).map_err(|e| FileError::from_std(e, self.path))?;
+
+        if result.len() < n {
+            return Err(self.invalid_file());
+        }
+
+        Ok(result)
+    }
+
+    fn len(&mut self) -> Result<usize, FileError> {
+        self.exact().map(|b| u64::from_le_bytes(b) as usize)
+    }
+
+    fn optional_len(&mut self) -> Result<Option<usize>, FileError> {
+        self.optional().map(|b| b.map(|b| u64::from_le_bytes(b) as usize))
+    }
+
+    fn unit(&mut self) -> Result<Unit, FileError> {
+        self.exact().map(Unit::from_le_bytes)
+    }
+
+    fn optional_unit(&mut self) -> Result<Option<Unit>, FileError> {
+        self.optional().map(|b| b.map(Unit::from_le_bytes))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, FileError> {
+        let len = self.len()?;
+        self.take(len)
+    }
+}
@@ -0,0 +1,82 @@
+use super::*;
+use crate::{DictionaryConfig, construct_dictionary, construct_dictionary_with_snapshots};
+use crate::files::{WriteMode, remove_file, write_bytes};
+use crate::test_utils::{corpus, temp_path};
+
+fn sample_corpus() -> Vec<u8> {
+    corpus(b". ")
+}
+
+#[test]
+fn checkpoint_roundtrip_test() {
+    let path = temp_path("bpe_rs_checkpoint_roundtrip_test");
+    let checkpoint = Checkpoint {
+        merges: vec![(b"a".to_vec(), b"b".to_vec()), (b"ab".to_vec(), b"\xff".to_vec())],
+        unit_map: vec![(0, b"a".as_slice().into()), (256, b"ab".as_slice().into())].into_iter().collect(),
+        units: Units::from_units(vec![256, 0, 0, 256]),
+        protected: vec![256].into_iter().collect(),
+
+        // 257 was removed
+        allocator: UnitAllocator::starting_at(258),
+        input_length: 9,
+    };
+
+    checkpoint.save(&path).unwrap();
+    assert_eq!(Checkpoint::load(&path, None).unwrap(), checkpoint);
+
+    // the units go to the working file
+    let working_file = temp_path("bpe_rs_checkpoint_roundtrip_test.units");
+    let loaded = Checkpoint::load(&path, Some(&working_file)).unwrap();
+    assert!(matches!(loaded.units, Units::Mapped(_)));
+    assert_eq!(loaded, checkpoint);
+    drop(loaded);
+    assert!(!crate::files::exists(&working_file));
+
+    write_bytes(&path, &MAGIC[..3], WriteMode::CreateOrTruncate).unwrap();
+    assert!(Checkpoint::load(&path, None).is_err());
+    remove_file(&path).unwrap();
+}
+
+// resuming from a checkpoint gives the same result as an uninterrupted run
+#[test]
+fn resume_test() {
+    let path = temp_path("bpe_rs_resume_test");
+    let bytes = sample_corpus();
+    let config = DictionaryConfig::default()
+        .set_dictionary_size(300)
+        .set_checkpoint_file(Some(path.clone()))
+        .set_checkpoint_every_merges(Some(10))
+        .to_owned();
+
+    let uninterrupted = construct_dictionary(&bytes, config.clone());
+    let checkpoint = Checkpoint::load(&path, None).unwrap();
+
+    assert!(!checkpoint.merges.is_empty());
+    assert!(checkpoint.merges.len() < uninterrupted.merges().len());
+
+    // the input is not used when resuming
+    let resumed = construct_dictionary(
+        b"",
+        config.clone().set_resume_from_checkpoint(true).to_owned(),
+    );
+
+    assert!(resumed == uninterrupted);
+
+    // the checkpoint has already passed 257
+    let (_, snapshots) = construct_dictionary_with_snapshots(
+        b"",
+        config.clone().set_resume_from_checkpoint(true).set_snapshot_sizes(vec![257]).to_owned(),
+    );
+
+    assert!(snapshots.is_empty());
+
+    // a broken checkpoint is ignored, and it starts over
+    write_bytes(&path, &MAGIC[..3], WriteMode::CreateOrTruncate).unwrap();
+    let started_over = construct_dictionary(
+        &bytes,
+        config.clone().set_resume_from_checkpoint(true).set_checkpoint_every_merges(None).to_owned(),
+    );
+
+    assert!(started_over == uninterrupted);
+    remove_file(&path).unwrap();
+}
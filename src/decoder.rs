@@ -0,0 +1,91 @@
+use crate::dictionary::{Dictionary, TokenIds};
+use crate::normalizer::{Normalizer, WHITESPACE_MARKER};
+
+#[cfg(test)]
+mod tests;
+
+/// Incremental decoder: it takes ids one by one (e.g. tokens generated by a model), and returns the text
+/// as soon as it's complete. A character that's split across tokens is returned with its last byte.\
+/// Like `Dictionary::denormalize`, it undoes `WhitespaceMarker`.
+pub struct Decoder<'d> {
+    dictionary: &'d Dictionary,
+    ids: TokenIds,
+
+    // an incomplete UTF-8 sequence at the end
+    buffer: Vec<u8>,
+
+    // nothing has been returned yet: the first `WHITESPACE_MARKER` has to be removed
+    at_start: bool,
+}
+
+impl<'d> Decoder<'d> {
+    pub fn new(dictionary: &'d Dictionary) -> Self {
+        Decoder {
+            dictionary,
+            ids: dictionary.token_ids(),
+            buffer: vec![],
+            at_start: true,
+        }
+    }
+
+    /// The text that's completed by `id`. It's `None` if `id` is not in `Dictionary::token_ids`.\
+    /// An invalid sequence that can never be completed becomes `U+FFFD`.
+    pub fn push(&mut self, id: u32) -> Option<String> {
+        self.buffer.extend_from_slice(self.ids.token(id)?);
+
+        let mut result = String::new();
+        let mut start = 0;
+
+        loop {
+            match std::str::from_utf8(&self.buffer[start..]) {
+                Ok(s) => {
+                    result.push_str(s);
+                    start = self.buffer.len();
+                    break;
+                },
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    result.push_str(std::str::from_utf8(&self.buffer[start..(start + valid)]).unwrap());
+                    start += valid;
+
+                    match e.error_len() {
+                        Some(len) => {
+                            result.push(char::REPLACEMENT_CHARACTER);
+                            start += len;
+                        },
+
+                        // it may be completed by the next token
+                        None => {
+                            break;
+                        },
+                    }
+                },
+            }
+        }
+
+        self.buffer.drain(..start);
+        Some(self.denormalize(result))
+    }
+
+    /// Bytes that are left in the buffer are an error: they're an incomplete character.
+    pub fn finish(self) -> Result<(), Vec<u8>> {
+        if self.buffer.is_empty() {
+            Ok(())
+        }
+
+        else {
+            Err(self.buffer)
+        }
+    }
+
+    fn denormalize(&mut self, s: String) -> String {
+        if !self.dictionary.normalizers().contains(&Normalizer::WhitespaceMarker) || s.is_empty() {
+            return s;
+        }
+
+        let s = if self.at_start { s.strip_prefix(WHITESPACE_MARKER).unwrap_or(&s) } else { &s };
+        self.at_start = false;
+
+        s.replace(WHITESPACE_MARKER, " ")
+    }
+}
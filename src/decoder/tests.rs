@@ -0,0 +1,42 @@
+use crate::{DictionaryConfig, Normalizer, construct_dictionary};
+use crate::test_utils::corpus;
+
+#[test]
+fn streaming_decode_test() {
+    let bytes = corpus(". 다람쥐 헌 쳇바퀴에 타고파. ".as_bytes());
+
+    let dictionary = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_dictionary_size(400)
+            .set_normalizers(vec![Normalizer::WhitespaceMarker])
+            .to_owned(),
+    );
+    let input = "the lazy 쥐 jumps over 🦊 다람쥐";
+    let encoding = dictionary.tokenize(input.as_bytes());
+    let mut decoder = dictionary.decoder();
+    let mut result = String::new();
+
+    for id in encoding.ids.iter() {
+        let text = decoder.push(*id).unwrap();
+
+        // it never returns a part of a character
+        assert!(!text.contains(char::REPLACEMENT_CHARACTER));
+        result.push_str(&text);
+    }
+
+    assert_eq!(result, input);
+    assert_eq!(decoder.finish(), Ok(()));
+
+    // an incomplete character at the end, and an invalid one in the middle
+    let ids = dictionary.token_ids();
+    let mut decoder = dictionary.decoder();
+    let fox = "🦊".as_bytes();
+
+    assert_eq!(decoder.push(ids.id(b"a").unwrap()).unwrap(), "a");
+    assert_eq!(decoder.push(0xff).unwrap(), "\u{fffd}");
+    assert_eq!(decoder.push(fox[0] as u32).unwrap(), "");
+    assert_eq!(decoder.push(fox[1] as u32).unwrap(), "");
+    assert_eq!(decoder.push(u32::MAX), None);
+    assert_eq!(decoder.finish(), Err(fox[..2].to_vec()));
+}
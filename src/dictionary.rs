@@ -1,45 +1,132 @@
-use crate::bpe::{Unit, UnitMapInternal};
-use std::collections::HashMap;
+use crate::bpe::{StopReason, Unit, UnitMapInternal};
+use crate::decoder::Decoder;
+use crate::encoder::{Encoder, Encoding};
+use crate::normalizer::{NormalizedString, Normalizer, denormalize, normalize};
+use crate::unigram::{LogProbs, viterbi};
+use crate::wordpiece::{CONTINUATION_PREFIX, UNKNOWN_TOKEN, encode_word, split_word_ranges, split_words};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 mod config;
+mod file;
+mod ids;
+mod prune;
+mod segmentation;
 
-pub use config::DictionaryConfig;
+#[cfg(test)]
+mod tests;
 
-// TODO: serde file
+pub use config::{DictionaryConfig, Model};
+pub use file::HEADER;
+pub use ids::TokenIds;
+pub use prune::PruneReport;
+pub use segmentation::{SegmentationObjective, Segmenter};
+
+/// When encoding with `Model::Unigram`, a byte that's not in the dictionary gets
+/// the smallest log probability in the dictionary minus this value.
+pub const UNKNOWN_BYTE_PENALTY: f64 = 10.0;
+
+#[derive(Clone, PartialEq)]
 pub struct Dictionary {
     words: HashMap<Vec<u8>, usize>,  // <words, appearance>
+
+    // in the order they were merged
+    merges: Vec<(Vec<u8>, Vec<u8>)>,
+
+    // the ones that were used when training
+    normalizers: Vec<Normalizer>,
+
+    // only for `Model::Unigram`
+    log_probs: LogProbs,
+
+    // why the training stopped (only for `Model::Bpe`)
+    stop_reason: Option<StopReason>,
+
+    // the one that it's trained with
+    model: Model,
+
+    // multi-byte tokens whose ids are kept (the ones of `initial_dictionary`), in the order of the ids.
+    // See `Dictionary::token_ids`.
+    id_order: Vec<Vec<u8>>,
 }
 
 impl Dictionary {
     pub fn empty() -> Self {
-        Dictionary { words: HashMap::new() }
+        Dictionary {
+            words: HashMap::new(),
+            merges: vec![],
+            normalizers: vec![],
+            log_probs: HashMap::new(),
+            stop_reason: None,
+            model: Model::Bpe,
+            id_order: vec![],
+        }
+    }
+
+    pub fn from_units<I: IntoIterator<Item = Unit>>(
+        units: I,
+        unit_map: &UnitMapInternal,
+        merges: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Self {
+        let mut counts = HashMap::with_capacity(unit_map.len());
+
+        for unit in units {
+            *counts.entry(unit).or_insert(0) += 1;
+        }
+
+        Dictionary::from_unit_counts(&counts, unit_map, merges)
     }
 
-    pub fn from_units(units: &[Unit], unit_map: &UnitMapInternal) -> Self {
+    /// `from_units`, with how many times each unit appears (e.g. `Units::counts`)
+    pub fn from_unit_counts(
+        counts: &HashMap<Unit, usize>,
+        unit_map: &UnitMapInternal,
+        merges: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Self {
         let mut words = HashMap::with_capacity(unit_map.len());
 
-        for unit in units.iter() {
-            let word = unit_map.get(unit).unwrap().to_vec();
+        for (unit, count) in counts.iter() {
+            *words.entry(unit_map.get(unit).unwrap().to_vec()).or_insert(0) += *count;
+        }
 
-            match words.get_mut(&word) {
-                Some(n) => {
-                    *n += 1;
-                },
-                None => {
-                    words.insert(word, 1);
-                },
+        // if `keep_single_byte_tokens` is on or there's an `initial_dictionary`,
+        // there can be tokens without any appearance
+        for bytes in unit_map.values() {
+            if !words.contains_key(bytes.as_slice()) {
+                words.insert(bytes.to_vec(), 0);
             }
         }
 
-        // if `keep_single_byte_tokens` is on, there can be tokens without any appearance
-        for byte in 0..256 {
-            if unit_map.contains_key(&byte) && !words.contains_key(&vec![byte as u8]) {
-                words.insert(vec![byte as u8], 0);
-            }
+        Dictionary {
+            words,
+            merges,
+            normalizers: vec![],
+            log_probs: HashMap::new(),
+            stop_reason: None,
+            model: Model::Bpe,
+            id_order: vec![],
         }
+    }
 
-        Dictionary { words }
+    /// A dictionary without merges or log probabilities.
+    pub fn from_words(words: HashMap<Vec<u8>, usize>, model: Model) -> Self {
+        Dictionary {
+            words,
+            merges: vec![],
+            normalizers: vec![],
+            log_probs: HashMap::new(),
+            stop_reason: None,
+            model,
+            id_order: vec![],
+        }
+    }
+
+    /// `Model::Unigram` dictionary
+    pub fn from_log_probs(words: HashMap<Vec<u8>, usize>, log_probs: LogProbs) -> Self {
+        Dictionary {
+            log_probs,
+            ..Dictionary::from_words(words, Model::Unigram)
+        }
     }
 
     pub fn get_words_as_strings(&self) -> Vec<String> {
@@ -48,20 +135,93 @@ impl Dictionary {
         ).collect()
     }
 
-    pub fn iter(&self) -> std::collections::hash_map::Iter<Vec<u8>, usize> {
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, Vec<u8>, usize> {
         self.words.iter()
     }
 
     pub fn get<Q>(&self, word: &Q) -> Option<usize>
-    where Vec<u8>: std::borrow::Borrow<Q>, Q: Eq + std::hash::Hash {
-        self.words.get(word).map(|appearance| *appearance)
+    where Vec<u8>: std::borrow::Borrow<Q>, Q: Eq + std::hash::Hash + ?Sized {
+        self.words.get(word).copied()
+    }
+
+    /// pairs of tokens, in the order they were merged
+    pub fn merges(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.merges
+    }
+
+    /// It's empty unless the dictionary is trained with `Model::Unigram`.
+    pub fn log_probs(&self) -> &LogProbs {
+        &self.log_probs
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn normalizers(&self) -> &[Normalizer] {
+        &self.normalizers
+    }
+
+    /// Why `construct_dictionary` or `Trainer` stopped. It's `None` if the training was stopped by the caller
+    /// or the dictionary is made some other way (e.g. `Model::Unigram` or `Dictionary::merge`).
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    pub(crate) fn set_stop_reason(&mut self, reason: Option<StopReason>) -> &mut Self {
+        self.stop_reason = reason;
+
+        self
+    }
+
+    /// The tokens of `initial` keep their ids. See `Dictionary::token_ids`.
+    pub(crate) fn keep_ids_of(&mut self, initial: &Dictionary) -> &mut Self {
+        self.id_order = initial.token_ids().iter().skip(256).map(|(_, token)| token.to_vec()).collect();
+
+        self
+    }
+
+    pub fn set_normalizers(&mut self, normalizers: Vec<Normalizer>) -> &mut Self {
+        self.normalizers = normalizers;
+
+        self
+    }
+
+    /// It applies the normalizers that were used when training.
+    pub fn normalize(&self, s: &[u8]) -> NormalizedString {
+        normalize(s, &self.normalizers)
+    }
+
+    /// It undoes the reversible normalizers, such as `Normalizer::WhitespaceMarker`.
+    pub fn denormalize(&self, s: &[u8]) -> Vec<u8> {
+        denormalize(s, &self.normalizers)
     }
 
     pub fn len(&self) -> usize {
         self.words.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The dictionaries have to be trained with the same model, unless one of them is empty.
     pub fn merge(&mut self, other: &Dictionary) {
+        if self.words.is_empty() && self.merges.is_empty() {
+            self.model = other.model;
+            self.id_order = other.id_order.clone();
+        }
+
+        else if !other.words.is_empty() || !other.merges.is_empty() {
+            assert_eq!(
+                self.model,
+                other.model,
+                "cannot merge a `Model::{:?}` dictionary into a `Model::{:?}` one",
+                other.model,
+                self.model,
+            );
+        }
+
         for (word, appearance) in other.iter() {
             match self.words.get_mut(word) {
                 Some(n) => {
@@ -72,24 +232,134 @@ impl Dictionary {
                 },
             }
         }
+
+        // parents of a merge always come before the merge itself, so the order is kept
+        let mut merged = self.merges.iter().map(
+            |(w1, w2)| [w1.as_slice(), w2.as_slice()].concat()
+        ).collect::<HashSet<_>>();
+
+        for (w1, w2) in other.merges.iter() {
+            if merged.insert([w1.as_slice(), w2.as_slice()].concat()) {
+                self.merges.push((w1.to_vec(), w2.to_vec()));
+            }
+        }
+
+        // log probabilities are re-estimated from the merged appearances
+        if !self.log_probs.is_empty() || !other.log_probs.is_empty() {
+            for word in other.log_probs.keys() {
+                self.log_probs.insert(word.to_vec(), 0.0);
+            }
+
+            let total = self.log_probs.keys().map(
+                |word| self.get(word).unwrap_or(0).max(1)
+            ).sum::<usize>() as f64;
+
+            for (word, lp) in self.log_probs.iter_mut() {
+                *lp = (self.words.get(word).copied().unwrap_or(0).max(1) as f64 / total).ln();
+            }
+        }
+    }
+
+    /// Merge-rank BPE encoder (the dictionary has to be trained with `Model::Bpe`).
+    pub fn encoder(&self) -> Encoder<'_> {
+        Encoder::new(self)
+    }
+
+    /// Incremental decoder of `token_ids`.
+    pub fn decoder(&self) -> Decoder<'_> {
+        Decoder::new(self)
     }
 
-    /// a `u32` value represents a token\
-    pub fn tokenize(&self, s: &[u8]) -> (Vec<u32>, HashMap<u32, Vec<u8>>) {
-        let mut ordered_words = self.words.iter().collect::<Vec<_>>();
-        ordered_words.sort_by_key(|(_, appearance)| usize::MAX - *appearance);
+    /// Segmentation with the highest probability, using `log_probs` (the dictionary has to be trained with `Model::Unigram`).\
+    /// Bytes that are not in the dictionary become single-byte tokens.
+    pub fn encode_viterbi(&self, s: &[u8]) -> Vec<Vec<u8>> {
+        self.assert_model(Model::Unigram);
+        let normalized = self.normalize(s).bytes;
+
+        self.viterbi_spans(&normalized).into_iter().map(
+            |(start, end)| normalized[start..end].to_vec()
+        ).collect()
+    }
 
-        // TODO: below is just a pseudo-code
-        //       I don't think this algorithm makes much sense
-        // for (word, _) in ordered_words.iter() {
-        //     for c in s.chunks() {
-        //         if c == word {
-        //             replace(c, word)
-        //         }
-        //     }
-        // }
+    /// `encode_viterbi`, with the ids and the offsets of the tokens.
+    pub fn tokenize_viterbi(&self, s: &[u8]) -> Encoding {
+        self.assert_model(Model::Unigram);
+        let normalized = self.normalize(s);
+        let token_ids = self.token_ids();
+        let ids = self.viterbi_spans(&normalized.bytes).into_iter().map(
+            |(start, end)| token_ids.id(&normalized.bytes[start..end]).unwrap()
+        ).collect();
 
-        todo!()
+        Encoding::new(s, &normalized, ids, &token_ids)
+    }
+
+    // (start, end) of the tokens
+    fn viterbi_spans(&self, normalized: &[u8]) -> Vec<(usize, usize)> {
+        let max_len = self.log_probs.keys().map(|word| word.len()).max().unwrap_or(1);
+        let unknown_log_prob = self.log_probs.values().fold(0.0, |a: f64, b| a.min(*b)) - UNKNOWN_BYTE_PENALTY;
+
+        viterbi(normalized, &self.log_probs, max_len, Some(unknown_log_prob), false)
+    }
+
+    /// Greedy longest-match-first encoding of each word (the dictionary has to be trained with `Model::WordPiece`).\
+    /// Tokens in the middle of a word start with `##`, and a word that cannot be encoded becomes `[UNK]`.
+    pub fn encode_wordpiece(&self, s: &[u8]) -> Vec<Vec<u8>> {
+        self.assert_model(Model::WordPiece);
+        let normalized = self.normalize(s).bytes;
+
+        split_words(&normalized, None).into_iter().flat_map(
+            |word| encode_word(word, &self.words)
+        ).collect()
+    }
+
+    /// `encode_wordpiece`, with the ids and the offsets of the tokens.\
+    /// The offsets of a token don't include its `##`, and `[UNK]` gets the offsets of the whole word.
+    pub fn tokenize_wordpiece(&self, s: &[u8]) -> Encoding {
+        self.assert_model(Model::WordPiece);
+        let normalized = self.normalize(s);
+        let token_ids = self.token_ids();
+        let mut ids = vec![];
+        let mut spans = vec![];
+
+        for (start, end) in split_word_ranges(&normalized.bytes, None) {
+            let pieces = encode_word(&normalized.bytes[start..end], &self.words);
+
+            if pieces == [UNKNOWN_TOKEN] {
+                ids.push(token_ids.id(UNKNOWN_TOKEN).unwrap());
+                spans.push((start, end));
+                continue;
+            }
+
+            let mut index = start;
+
+            for piece in pieces.iter() {
+                let len = match index == start {
+                    true => piece.len(),
+                    false => piece.len() - CONTINUATION_PREFIX.len(),
+                };
+
+                ids.push(token_ids.id(piece).unwrap());
+                spans.push((index, index + len));
+                index += len;
+            }
+        }
+
+        Encoding::with_spans(s, &normalized, ids, &spans, &token_ids)
+    }
+
+    pub(crate) fn assert_model(&self, model: Model) {
+        assert_eq!(self.model, model, "the dictionary is trained with `Model::{:?}`", self.model);
+    }
+
+    /// It encodes with the model of the dictionary, with the ids and the offsets of the tokens:
+    /// merge-rank BPE (`Model::Bpe`), `tokenize_viterbi` (`Model::Unigram`) or `tokenize_wordpiece` (`Model::WordPiece`).\
+    /// It builds the ids (and the encoder) every time, so use `Dictionary::encoder` to encode many inputs with `Model::Bpe`.
+    pub fn tokenize(&self, s: &[u8]) -> Encoding {
+        match self.model {
+            Model::Bpe => self.encoder().tokenize(s),
+            Model::Unigram => self.tokenize_viterbi(s),
+            Model::WordPiece => self.tokenize_wordpiece(s),
+        }
     }
 }
 
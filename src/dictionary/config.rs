@@ -1,5 +1,14 @@
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+use super::Dictionary;
+use crate::bpe::Scoring;
+use regex::bytes::Regex;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use crate::normalizer::{Normalizer, marker_only_at_start};
+
+#[derive(Clone, Debug)]
 pub struct DictionaryConfig {
+    pub model: Model,
+
     /// It guarantees that the result of `construct_dictionary` is smaller than or equal to `dictionary_size`.
     pub dictionary_size: usize,
 
@@ -10,9 +19,73 @@ pub struct DictionaryConfig {
     /// Even though this value is set, the result dictionary may contain entries whose `appearance` is less than this value.
     pub minimum_appearance: Option<usize>,
 
+    /// How to choose a pair to merge (only for `Model::Bpe`).
+    pub scoring: Scoring,
+
+    /// (only for `Model::Bpe`)\
+    /// It stops if the last `gain_window` merges made the data shorter by less than `minimum_gain`,
+    /// which is a ratio to the length of the input (0.0 ~ 1.0). It's disabled if `gain_window` is 0.
+    pub minimum_gain: Option<f64>,
+    pub gain_window: usize,
+
+    /// (only for `Model::Bpe`)\
+    /// It stops when this much time has passed since the training began. In `dir_option`, it's per chunk.
+    pub time_limit: Option<Duration>,
+
+    /// (only for `Model::Bpe`)\
+    /// It stops when the resident memory of the process exceeds this many bytes.
+    /// It's ignored on platforms where the resident memory cannot be read (it reads `/proc/self/status`).
+    /// It's checked at most once every 100 milliseconds.
+    pub memory_limit: Option<usize>,
+
     /// This byte is never included in any multi-byte token.
     pub ultimate_separator: Option<u8>,
 
+    /// They're applied to the input, in this order, before training.
+    /// The result dictionary remembers them, so that the same steps are applied when encoding.
+    pub normalizers: Vec<Normalizer>,
+
+    /// If it's None, the initial units are bytes.\
+    /// Otherwise, the initial units are the most frequent characters that cover this ratio (0.0 ~ 1.0) of the input,
+    /// like `character_coverage` of SentencePiece. The other characters fall back to bytes.
+    pub character_coverage: Option<f64>,
+
+    /// (in bytes)\
+    /// It never makes a token longer than this.
+    pub maximum_token_length: Option<usize>,
+
+    /// If it's true, a multi-byte token is either valid UTF-8 or a part of a single character.
+    /// That means a token never ends in the middle of a character that it doesn't start with.
+    pub respect_utf8_boundary: bool,
+
+    /// If it's true, a token never contains both letters and digits.
+    pub separate_letters_and_digits: bool,
+
+    /// If it's true, a newline in a token can only be followed by whitespaces.
+    pub whitespace_after_newline: bool,
+
+    /// It continues training from this dictionary (only for `Model::Bpe`).
+    /// Its merges are applied to the input first, then new merges are added until `dictionary_size` is reached.
+    /// All the tokens in this dictionary are kept, and the merges keep their order.\
+    /// Its normalizers are not applied, so `normalizers` should be the same as the ones of this dictionary.
+    /// Its tokens that are not made from bytes (or characters of `character_coverage`) by its merges never appear,
+    /// and they're logged.
+    pub initial_dictionary: Option<Dictionary>,
+
+    /// (only for `Model::Bpe`)\
+    /// They're always in the result dictionary as single tokens, and they count toward `dictionary_size`.
+    /// They're merged from left to right before training, so other constraints (e.g. `maximum_token_length`) don't apply to them.
+    /// It includes `forbidden_tokens` and `forbidden_patterns`: a forced token and its prefixes that are made on the way
+    /// (`a` + `b`, `ab` + `c`, ...) are made even if they're forbidden.
+    /// If there are `normalizers`, the tokens have to be normalized ones.
+    pub forced_tokens: Vec<Vec<u8>>,
+
+    /// A token that contains any of these is never made.
+    pub forbidden_tokens: Vec<Vec<u8>>,
+
+    /// A token that any of these matches is never made. A pattern matches anywhere in the token unless it's anchored (`^...$`).
+    pub forbidden_patterns: Vec<Regex>,
+
     /// It's ignored if you're constructing a dictionary from raw input.
     pub dir_option: DirOption,
 
@@ -20,15 +93,57 @@ pub struct DictionaryConfig {
     /// If it's None, it chooses the best number.
     pub parallel_worker_count: Option<usize>,
 
+    /// (only for `Model::Bpe`)\
+    /// If it's set, the training data lives in a memory-mapped file at this path, instead of memory.
+    /// It's a working file, and is removed when the training is over. In `dir_option`, each chunk uses `{disk_backed_at}.{chunk index}`.
+    /// See `construct_dictionary_from_files` for the input that doesn't fit in memory.
+    pub disk_backed_at: Option<String>,
+
+    /// (only for `Model::Bpe`)\
+    /// Number of threads that count and replace pairs in a single `construct_dictionary` call.
+    /// The result is the same regardless of this value. If it's None, it uses a single thread.
+    pub thread_count: Option<usize>,
+
     /// Path to the log file
     /// It truncates the old file if exists
     pub write_log_at: Option<String>,
 
     // TODO: it only works at parallel mode, i have to implement one for single-threaded mode
     pub dump_result_at: Option<String>,
+
+    /// (only for `Model::Bpe`)\
+    /// It takes snapshots of the dictionary when it reaches these sizes.
+    /// A snapshot is the same as the result of a run whose `dictionary_size` is the size of the snapshot.
+    /// When it resumes from a checkpoint, the sizes that the checkpoint has already reached are skipped.
+    pub snapshot_sizes: Vec<usize>,
+
+    /// Snapshots are saved at `{snapshot_at}.{size}`.
+    pub snapshot_at: Option<String>,
+
+    /// Path to the checkpoint file (only for `Model::Bpe`).\
+    /// In single-threaded mode, it saves the merges and the current units.
+    /// In parallel mode, the master saves the merged dictionary and the finished chunks,
+    /// and each worker saves its own checkpoint at `{checkpoint_at}.chunk{index}`.
+    pub checkpoint_at: Option<String>,
+
+    /// It saves a checkpoint every N merges.
+    pub checkpoint_every_merges: Option<usize>,
+
+    /// It saves a checkpoint if this much time has passed since the last one.
+    pub checkpoint_interval: Option<Duration>,
+
+    /// If it's true and `checkpoint_at` exists, it resumes training from the checkpoint.
+    /// The config has to be the same as the one that made the checkpoint.
+    pub resume_from_checkpoint: bool,
 }
 
 impl DictionaryConfig {
+    pub fn set_model(&mut self, model: Model) -> &mut Self {
+        self.model = model;
+
+        self
+    }
+
     pub fn set_dictionary_size(&mut self, size: usize) -> &mut Self {
         self.dictionary_size = size;
 
@@ -47,12 +162,103 @@ impl DictionaryConfig {
         self
     }
 
+    pub fn set_scoring(&mut self, scoring: Scoring) -> &mut Self {
+        self.scoring = scoring;
+
+        self
+    }
+
+    pub fn set_minimum_gain(&mut self, window: usize, minimum: Option<f64>) -> &mut Self {
+        self.gain_window = window;
+        self.minimum_gain = minimum;
+
+        self
+    }
+
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) -> &mut Self {
+        self.time_limit = limit;
+
+        self
+    }
+
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) -> &mut Self {
+        self.memory_limit = limit;
+
+        self
+    }
+
     pub fn set_ultimate_separator(&mut self, separator: Option<u8>) -> &mut Self {
         self.ultimate_separator = separator;
 
         self
     }
 
+    pub fn set_normalizers(&mut self, normalizers: Vec<Normalizer>) -> &mut Self {
+        self.normalizers = normalizers;
+
+        self
+    }
+
+    pub fn add_normalizer(&mut self, normalizer: Normalizer) -> &mut Self {
+        self.normalizers.push(normalizer);
+
+        self
+    }
+
+    pub fn set_character_coverage(&mut self, coverage: Option<f64>) -> &mut Self {
+        self.character_coverage = coverage;
+
+        self
+    }
+
+    pub fn set_maximum_token_length(&mut self, length: Option<usize>) -> &mut Self {
+        self.maximum_token_length = length;
+
+        self
+    }
+
+    pub fn set_respect_utf8_boundary(&mut self, respect: bool) -> &mut Self {
+        self.respect_utf8_boundary = respect;
+
+        self
+    }
+
+    pub fn set_separate_letters_and_digits(&mut self, separate: bool) -> &mut Self {
+        self.separate_letters_and_digits = separate;
+
+        self
+    }
+
+    pub fn set_whitespace_after_newline(&mut self, whitespace_only: bool) -> &mut Self {
+        self.whitespace_after_newline = whitespace_only;
+
+        self
+    }
+
+    pub fn set_initial_dictionary(&mut self, dictionary: Option<Dictionary>) -> &mut Self {
+        self.initial_dictionary = dictionary;
+
+        self
+    }
+
+    pub fn set_forced_tokens(&mut self, tokens: Vec<Vec<u8>>) -> &mut Self {
+        self.forced_tokens = tokens;
+
+        self
+    }
+
+    pub fn set_forbidden_tokens(&mut self, tokens: Vec<Vec<u8>>) -> &mut Self {
+        self.forbidden_tokens = tokens;
+
+        self
+    }
+
+    pub fn set_forbidden_patterns(&mut self, patterns: Vec<Regex>) -> &mut Self {
+        self.forbidden_patterns = patterns;
+
+        self
+    }
+
     pub fn set_dir(&mut self, dir: String) -> &mut Self {
         self.dir_option.path = dir;
 
@@ -89,28 +295,330 @@ impl DictionaryConfig {
         self
     }
 
+    pub fn set_snapshot_sizes(&mut self, sizes: Vec<usize>) -> &mut Self {
+        self.snapshot_sizes = sizes;
+
+        self
+    }
+
+    pub fn set_snapshot_file(&mut self, snapshot_file: Option<String>) -> &mut Self {
+        self.snapshot_at = snapshot_file;
+
+        self
+    }
+
+    pub fn set_checkpoint_file(&mut self, checkpoint_file: Option<String>) -> &mut Self {
+        self.checkpoint_at = checkpoint_file;
+
+        self
+    }
+
+    pub fn set_checkpoint_every_merges(&mut self, merges: Option<usize>) -> &mut Self {
+        self.checkpoint_every_merges = merges;
+
+        self
+    }
+
+    pub fn set_checkpoint_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.checkpoint_interval = interval;
+
+        self
+    }
+
+    pub fn set_resume_from_checkpoint(&mut self, resume: bool) -> &mut Self {
+        self.resume_from_checkpoint = resume;
+
+        self
+    }
+
     pub fn set_worker_count(&mut self, worker_count: Option<usize>) -> &mut Self {
         self.parallel_worker_count = worker_count;
 
         self
     }
+
+    pub fn set_disk_backed_file(&mut self, path: Option<String>) -> &mut Self {
+        self.disk_backed_at = path;
+
+        self
+    }
+
+    pub fn set_thread_count(&mut self, thread_count: Option<usize>) -> &mut Self {
+        self.thread_count = thread_count;
+
+        self
+    }
+
+    /// It checks the structural constraints (`maximum_token_length`, `respect_utf8_boundary`, ...) of a new token.
+    pub fn allows_token(&self, token: &[u8]) -> bool {
+        if let Some(length) = self.maximum_token_length {
+            if token.len() > length {
+                return false;
+            }
+        }
+
+        if self.respect_utf8_boundary && !is_utf8_fragment(token) {
+            return false;
+        }
+
+        if self.separate_letters_and_digits {
+            let s = String::from_utf8_lossy(token);
+
+            if s.chars().any(|c| c.is_alphabetic()) && s.chars().any(|c| c.is_numeric()) {
+                return false;
+            }
+        }
+
+        if self.normalizers.contains(&Normalizer::WhitespaceMarker) && !marker_only_at_start(token) {
+            return false;
+        }
+
+        if self.whitespace_after_newline {
+            for w in token.windows(2) {
+                if w[0] == b'\n' && !w[1].is_ascii_whitespace() {
+                    return false;
+                }
+            }
+        }
+
+        for forbidden in self.forbidden_tokens.iter() {
+            if !forbidden.is_empty() && token.windows(forbidden.len()).any(|w| w == forbidden.as_slice()) {
+                return false;
+            }
+        }
+
+        if self.forbidden_patterns.iter().any(|pattern| pattern.is_match(token)) {
+            return false;
+        }
+
+        true
+    }
+}
+
+// valid UTF-8, or a part of a single character
+fn is_utf8_fragment(bytes: &[u8]) -> bool {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+
+        // it starts with an incomplete character
+        Err(e) if e.valid_up_to() == 0 => match e.error_len() {
+            // the whole thing is a prefix of a character
+            None => true,
+
+            // continuation bytes only (the middle or the end of a character)
+            Some(_) => bytes.len() < 4 && bytes.iter().all(|b| b & 0b1100_0000 == 0b1000_0000),
+        },
+
+        // it has a complete character and an incomplete one
+        Err(_) => false,
+    }
 }
 
 impl Default for DictionaryConfig {
     fn default() -> Self {
         DictionaryConfig {
+            model: Model::Bpe,
             dictionary_size: 2048,
             keep_single_byte_tokens: true,
             minimum_appearance: Some(3),
+            scoring: Scoring::Frequency,
+            minimum_gain: None,
+            gain_window: 64,
+            time_limit: None,
+            memory_limit: None,
             ultimate_separator: None,
+            normalizers: vec![],
+            character_coverage: None,
+            maximum_token_length: None,
+            respect_utf8_boundary: false,
+            separate_letters_and_digits: false,
+            whitespace_after_newline: false,
+            initial_dictionary: None,
+            forced_tokens: vec![],
+            forbidden_tokens: vec![],
+            forbidden_patterns: vec![],
             dir_option: DirOption::default(),
             parallel_worker_count: None,
+            disk_backed_at: None,
+            thread_count: None,
             write_log_at: None,
             dump_result_at: None,
+            snapshot_sizes: vec![],
+            snapshot_at: None,
+            checkpoint_at: None,
+            checkpoint_every_merges: None,
+            checkpoint_interval: None,
+            resume_from_checkpoint: false,
         }
     }
 }
 
+// `f64` fields are compared by their bits, so that it can be `Eq` and `Hash`.
+// The fields are destructured, so that a new field cannot be left out.
+impl PartialEq for DictionaryConfig {
+    fn eq(&self, other: &DictionaryConfig) -> bool {
+        let DictionaryConfig {
+            model,
+            dictionary_size,
+            keep_single_byte_tokens,
+            minimum_appearance,
+            scoring,
+            minimum_gain,
+            gain_window,
+            time_limit,
+            memory_limit,
+            ultimate_separator,
+            normalizers,
+            character_coverage,
+            maximum_token_length,
+            respect_utf8_boundary,
+            separate_letters_and_digits,
+            whitespace_after_newline,
+            initial_dictionary,
+            forced_tokens,
+            forbidden_tokens,
+            forbidden_patterns,
+            dir_option,
+            parallel_worker_count,
+            disk_backed_at,
+            thread_count,
+            write_log_at,
+            dump_result_at,
+            snapshot_sizes,
+            snapshot_at,
+            checkpoint_at,
+            checkpoint_every_merges,
+            checkpoint_interval,
+            resume_from_checkpoint,
+        } = self;
+
+        *model == other.model
+            && *dictionary_size == other.dictionary_size
+            && *keep_single_byte_tokens == other.keep_single_byte_tokens
+            && *minimum_appearance == other.minimum_appearance
+            && *scoring == other.scoring
+            && minimum_gain.map(f64::to_bits) == other.minimum_gain.map(f64::to_bits)
+            && *gain_window == other.gain_window
+            && *time_limit == other.time_limit
+            && *memory_limit == other.memory_limit
+            && *ultimate_separator == other.ultimate_separator
+            && *normalizers == other.normalizers
+            && character_coverage.map(f64::to_bits) == other.character_coverage.map(f64::to_bits)
+            && *maximum_token_length == other.maximum_token_length
+            && *respect_utf8_boundary == other.respect_utf8_boundary
+            && *separate_letters_and_digits == other.separate_letters_and_digits
+            && *whitespace_after_newline == other.whitespace_after_newline
+            && *initial_dictionary == other.initial_dictionary
+            && *forced_tokens == other.forced_tokens
+            && *forbidden_tokens == other.forbidden_tokens
+
+            // `Regex` is compared by its pattern
+            && forbidden_patterns.iter().map(Regex::as_str).eq(other.forbidden_patterns.iter().map(Regex::as_str))
+            && *dir_option == other.dir_option
+            && *parallel_worker_count == other.parallel_worker_count
+            && *disk_backed_at == other.disk_backed_at
+            && *thread_count == other.thread_count
+            && *write_log_at == other.write_log_at
+            && *dump_result_at == other.dump_result_at
+            && *snapshot_sizes == other.snapshot_sizes
+            && *snapshot_at == other.snapshot_at
+            && *checkpoint_at == other.checkpoint_at
+            && *checkpoint_every_merges == other.checkpoint_every_merges
+            && *checkpoint_interval == other.checkpoint_interval
+            && *resume_from_checkpoint == other.resume_from_checkpoint
+    }
+}
+
+impl Eq for DictionaryConfig {}
+
+impl Hash for DictionaryConfig {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let DictionaryConfig {
+            model,
+            dictionary_size,
+            keep_single_byte_tokens,
+            minimum_appearance,
+            scoring,
+            minimum_gain,
+            gain_window,
+            time_limit,
+            memory_limit,
+            ultimate_separator,
+            normalizers,
+            character_coverage,
+            maximum_token_length,
+            respect_utf8_boundary,
+            separate_letters_and_digits,
+            whitespace_after_newline,
+            initial_dictionary,
+            forced_tokens,
+            forbidden_tokens,
+            forbidden_patterns,
+            dir_option,
+            parallel_worker_count,
+            disk_backed_at,
+            thread_count,
+            write_log_at,
+            dump_result_at,
+            snapshot_sizes,
+            snapshot_at,
+            checkpoint_at,
+            checkpoint_every_merges,
+            checkpoint_interval,
+            resume_from_checkpoint,
+        } = self;
+
+        model.hash(state);
+        dictionary_size.hash(state);
+        keep_single_byte_tokens.hash(state);
+        minimum_appearance.hash(state);
+        scoring.hash(state);
+        minimum_gain.map(f64::to_bits).hash(state);
+        gain_window.hash(state);
+        time_limit.hash(state);
+        memory_limit.hash(state);
+        ultimate_separator.hash(state);
+        normalizers.hash(state);
+        character_coverage.map(f64::to_bits).hash(state);
+        maximum_token_length.hash(state);
+        respect_utf8_boundary.hash(state);
+        separate_letters_and_digits.hash(state);
+        whitespace_after_newline.hash(state);
+
+        // a dictionary is not hashable. Equal configs still have the same hash without it.
+        initial_dictionary.is_some().hash(state);
+        forced_tokens.hash(state);
+        forbidden_tokens.hash(state);
+
+        for pattern in forbidden_patterns.iter() {
+            pattern.as_str().hash(state);
+        }
+        dir_option.hash(state);
+        parallel_worker_count.hash(state);
+        disk_backed_at.hash(state);
+        thread_count.hash(state);
+        write_log_at.hash(state);
+        dump_result_at.hash(state);
+        snapshot_sizes.hash(state);
+        snapshot_at.hash(state);
+        checkpoint_at.hash(state);
+        checkpoint_every_merges.hash(state);
+        checkpoint_interval.hash(state);
+        resume_from_checkpoint.hash(state);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Model {
+    Bpe,
+
+    /// SentencePiece-style Unigram language model
+    Unigram,
+
+    /// BERT-style WordPiece: tokens in the middle of a word start with `##`.
+    WordPiece,
+}
+
 /// It reads all the files with the given extension, in the given path.
 /// It does NOT search recursively.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -0,0 +1,184 @@
+use super::{Dictionary, Model};
+use crate::bpe::StopReason;
+use crate::files::{FileError, WriteMode, read_string, write_string};
+use crate::normalizer::Normalizer;
+use std::collections::HashMap;
+
+/// ```nohighlight
+/// # bpe-rs dictionary
+/// model Bpe
+/// normalizer Nfc
+/// stop DictionarySize
+/// word 7468 12
+/// word 7468 12 -3.25        (with a log probability)
+/// merge 74 68
+/// id 7468
+/// ```
+/// Bytes are hex-encoded. Merges are written in the order they were merged, and `id`s are the tokens whose ids
+/// are kept from `initial_dictionary`, in the order of the ids.
+/// A file without `model` is `Model::Unigram` if it has log probabilities, and `Model::Bpe` otherwise.
+pub const HEADER: &str = "# bpe-rs dictionary";
+
+impl Dictionary {
+    pub fn save(&self, path: &str) -> Result<(), FileError> {
+        write_string(path, &self.to_text(), WriteMode::CreateOrTruncate)
+    }
+
+    pub fn load(path: &str) -> Result<Self, FileError> {
+        Dictionary::from_text(&read_string(path)?, path)
+    }
+
+    /// contents of the dictionary file
+    pub(crate) fn to_text(&self) -> String {
+        let mut lines = vec![HEADER.to_string(), format!("model {:?}", self.model)];
+
+        for normalizer in self.normalizers.iter() {
+            lines.push(format!("normalizer {normalizer:?}"));
+        }
+
+        if let Some(reason) = self.stop_reason {
+            lines.push(format!("stop {reason:?}"));
+        }
+
+        let mut words = self.words.iter().collect::<Vec<_>>();
+        words.sort_by(|(w1, a1), (w2, a2)| a2.cmp(a1).then_with(|| w1.cmp(w2)));
+
+        for (word, appearance) in words.into_iter() {
+            match self.log_probs.get(word) {
+                Some(lp) => lines.push(format!("word {} {appearance} {lp}", to_hex(word))),
+                None => lines.push(format!("word {} {appearance}", to_hex(word))),
+            }
+        }
+
+        for (w1, w2) in self.merges.iter() {
+            lines.push(format!("merge {} {}", to_hex(w1), to_hex(w2)));
+        }
+
+        for token in self.id_order.iter() {
+            lines.push(format!("id {}", to_hex(token)));
+        }
+
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// `path` is only for the error message.
+    pub(crate) fn from_text(s: &str, path: &str) -> Result<Self, FileError> {
+        let mut result = Dictionary::empty();
+        let mut lines = s.lines();
+        let mut model = None;
+
+        if lines.next() != Some(HEADER) {
+            return Err(invalid_file(path, 1));
+        }
+
+        for (index, line) in lines.enumerate() {
+            let fields = line.split(' ').collect::<Vec<_>>();
+
+            match fields.as_slice() {
+                [] | [""] => {},
+                ["model", m] => {
+                    model = Some(parse_model(m).ok_or_else(|| invalid_file(path, index + 2))?);
+                },
+                ["normalizer", normalizer] => {
+                    result.normalizers.push(
+                        parse_normalizer(normalizer).ok_or_else(|| invalid_file(path, index + 2))?
+                    );
+                },
+                ["stop", reason] => {
+                    result.stop_reason = Some(
+                        parse_stop_reason(reason).ok_or_else(|| invalid_file(path, index + 2))?
+                    );
+                },
+                ["word", word, appearance, rest @ ..] if rest.len() < 2 => {
+                    let word = from_hex(word).ok_or_else(|| invalid_file(path, index + 2))?;
+                    let appearance = appearance.parse::<usize>().map_err(|_| invalid_file(path, index + 2))?;
+
+                    if let [lp] = rest {
+                        let lp = lp.parse::<f64>().map_err(|_| invalid_file(path, index + 2))?;
+                        result.log_probs.insert(word.clone(), lp);
+                    }
+
+                    result.words.insert(word, appearance);
+                },
+                ["merge", w1, w2] => {
+                    let w1 = from_hex(w1).ok_or_else(|| invalid_file(path, index + 2))?;
+                    let w2 = from_hex(w2).ok_or_else(|| invalid_file(path, index + 2))?;
+
+                    result.merges.push((w1, w2));
+                },
+                ["id", token] => {
+                    result.id_order.push(from_hex(token).ok_or_else(|| invalid_file(path, index + 2))?);
+                },
+                _ => {
+                    return Err(invalid_file(path, index + 2));
+                },
+            }
+        }
+
+        result.model = match model {
+            Some(model) => model,
+            None if !result.log_probs.is_empty() => Model::Unigram,
+            None => Model::Bpe,
+        };
+
+        Ok(result)
+    }
+}
+
+fn invalid_file(path: &str, line: usize) -> FileError {
+    FileError::unknown(
+        format!("invalid dictionary file (line {line})"),
+        Some(path.to_string()),
+    )
+}
+
+fn parse_normalizer(s: &str) -> Option<Normalizer> {
+    let normalizers = [
+        Normalizer::Nfc,
+        Normalizer::Nfkc,
+        Normalizer::Lowercase,
+        Normalizer::StripAccents,
+        Normalizer::CollapseWhitespace,
+        Normalizer::ReplaceControlCharacters,
+        Normalizer::WhitespaceMarker,
+    ].into_iter().map(
+        |normalizer| (format!("{normalizer:?}"), normalizer)
+    ).collect::<HashMap<_, _>>();
+
+    normalizers.get(s).copied()
+}
+
+fn parse_model(s: &str) -> Option<Model> {
+    [
+        Model::Bpe,
+        Model::Unigram,
+        Model::WordPiece,
+    ].into_iter().find(|model| format!("{model:?}") == s)
+}
+
+fn parse_stop_reason(s: &str) -> Option<StopReason> {
+    [
+        StopReason::DictionarySize,
+        StopReason::MinimumAppearance,
+        StopReason::TooShort,
+        StopReason::GainPlateau,
+        StopReason::TimeLimit,
+        StopReason::MemoryLimit,
+        StopReason::FileError,
+    ].into_iter().find(|reason| format!("{reason:?}") == s)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len()).step_by(2).map(
+        |i| s.get(i..(i + 2)).and_then(|byte| u8::from_str_radix(byte, 16).ok())
+    ).collect()
+}
@@ -0,0 +1,104 @@
+use super::Dictionary;
+use std::collections::{HashMap, HashSet};
+
+/// Dense ids of the tokens of a `Dictionary`. See `Dictionary::token_ids`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenIds {
+    // id -> token
+    tokens: Vec<Vec<u8>>,
+    ids: HashMap<Vec<u8>, u32>,
+
+    // index in `Dictionary::merges` of the merge that makes each token (by id)
+    merge_ranks: Vec<Option<usize>>,
+}
+
+impl TokenIds {
+    pub fn id(&self, token: &[u8]) -> Option<u32> {
+        self.ids.get(token).copied()
+    }
+
+    pub fn token(&self, id: u32) -> Option<&[u8]> {
+        self.tokens.get(id as usize).map(|token| token.as_slice())
+    }
+
+    /// If the token is made by a merge, it's the index of the merge in `Dictionary::merges`.
+    pub fn merge_rank(&self, id: u32) -> Option<usize> {
+        self.merge_ranks.get(id as usize).copied().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// (id, token), in the order of the ids
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.tokens.iter().enumerate().map(|(id, token)| (id as u32, token.as_slice()))
+    }
+}
+
+impl Dictionary {
+    /// It numbers the tokens from 0, without gaps, in the order they were made. The ids only depend on the tokens,
+    /// the merges and the order that the dictionary keeps, so the same dictionary always gets the same ids
+    /// (e.g. after it's saved and loaded).
+    ///
+    /// 1. 0 ~ 255: single bytes, whether the dictionary has them or not
+    /// 2. tokens of `initial_dictionary` (if it's trained with one), in the order of their ids in the initial dictionary
+    /// 3. for each merge, in the order of `Dictionary::merges`: its multi-byte operands that don't have ids yet
+    ///    (e.g. characters of `character_coverage`), then the token it makes
+    /// 4. multi-byte tokens that are neither made nor used by a merge, sorted by bytes
+    ///
+    /// So extending a dictionary with `initial_dictionary` never changes the ids of its tokens.
+    ///
+    /// Tokens that are only used by merges (e.g. the ones that are removed after training) get ids too,
+    /// because the encoder can still make them.
+    pub fn token_ids(&self) -> TokenIds {
+        let mut tokens = (0..=255).map(|byte| vec![byte]).collect::<Vec<_>>();
+        let mut ids = tokens.iter().enumerate().map(|(id, token)| (token.clone(), id as u32)).collect::<HashMap<_, _>>();
+        let mut merge_ranks = vec![None; tokens.len()];
+
+        // `Dictionary::merge` may leave two merges that make the same token: the first one wins
+        let mut made_by = HashMap::with_capacity(self.merges.len());
+        let mut operands = HashSet::with_capacity(self.merges.len());
+
+        for (rank, (w1, w2)) in self.merges.iter().enumerate() {
+            made_by.entry([w1.as_slice(), w2.as_slice()].concat()).or_insert(rank);
+            operands.insert(w1.as_slice());
+            operands.insert(w2.as_slice());
+        }
+
+        // it skips the ones that already have ids
+        let mut push = |token: &[u8]| {
+            if !ids.contains_key(token) {
+                ids.insert(token.to_vec(), tokens.len() as u32);
+                tokens.push(token.to_vec());
+                merge_ranks.push(made_by.get(token).copied());
+            }
+        };
+
+        // a token that's pruned since then doesn't get an id
+        for token in self.id_order.iter() {
+            if self.words.contains_key(token) || made_by.contains_key(token) || operands.contains(token.as_slice()) {
+                push(token);
+            }
+        }
+
+        for (w1, w2) in self.merges.iter() {
+            push(w1);
+            push(w2);
+            push(&[w1.as_slice(), w2.as_slice()].concat());
+        }
+
+        let mut words = self.words.keys().collect::<Vec<_>>();
+        words.sort();
+
+        for word in words.into_iter() {
+            push(word);
+        }
+
+        TokenIds { tokens, ids, merge_ranks }
+    }
+}
@@ -0,0 +1,157 @@
+use super::{Dictionary, Model};
+use crate::wordpiece::UNKNOWN_TOKEN;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Result of `Dictionary::prune_to`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PruneReport {
+    /// in the order they were dropped
+    pub dropped: Vec<Vec<u8>>,
+
+    /// bytes per token, estimated from the appearances of the tokens
+    pub compression_before: f64,
+    pub compression_after: f64,
+}
+
+impl Dictionary {
+    /// It drops the tokens with the fewest appearances until the dictionary has at most `size` tokens.
+    ///
+    /// A token is dropped only if no remaining merge uses it, and its merge is dropped with it,
+    /// so every remaining token can still be made by the merges. Appearances of a dropped token go to
+    /// the tokens it was merged from (or its bytes, if it was not made by a merge).
+    /// Single-byte tokens (and `[UNK]` of `Model::WordPiece`) are never dropped, so the result may be larger than `size`.
+    pub fn prune_to(&mut self, size: usize) -> PruneReport {
+        let compression_before = self.compression();
+
+        // merged token -> indexes of its merges (`Dictionary::merge` may leave more than one), in order
+        let mut merge_of = HashMap::<Vec<u8>, Vec<usize>>::with_capacity(self.merges.len());
+
+        // how many remaining merges use the token
+        let mut operand_count = HashMap::<Vec<u8>, usize>::new();
+
+        for (index, (w1, w2)) in self.merges.iter().enumerate() {
+            merge_of.entry([w1.as_slice(), w2.as_slice()].concat()).or_default().push(index);
+            *operand_count.entry(w1.to_vec()).or_insert(0) += 1;
+            *operand_count.entry(w2.to_vec()).or_insert(0) += 1;
+        }
+
+        let mut dropped_merges = HashSet::new();
+        let mut dropped = vec![];
+
+        let mut candidates = Candidates::new();
+
+        for (word, appearance) in self.words.iter() {
+            // the encoder falls back to it
+            if self.model == Model::WordPiece && word == UNKNOWN_TOKEN {
+                continue;
+            }
+
+            push_candidate(&mut candidates, word, *appearance, &merge_of, &operand_count);
+        }
+
+        while self.words.len() > size {
+            let Some(Reverse((appearance, _, token))) = candidates.pop() else {
+                break;
+            };
+
+            // the entry is outdated
+            if self.words.get(&token) != Some(&appearance) || operand_count.get(&token).copied().unwrap_or(0) > 0 {
+                continue;
+            }
+
+            self.words.remove(&token);
+            self.log_probs.remove(&token);
+
+            // (parent, whether `token` was merged from it, appearances that go to it)
+            // Every merge that makes `token` is dropped, but only the operands of the first one get the appearances:
+            // it's the one that the encoder applies.
+            let mut parents = match merge_of.get(&token) {
+                Some(indexes) => self.drop_merges(indexes, appearance, &mut dropped_merges),
+
+                // it falls back to bytes
+                None => token.iter().map(|byte| (vec![*byte], false, appearance)).collect(),
+            };
+
+            dropped.push(token);
+
+            while let Some((parent, merged_from, appearance)) = parents.pop() {
+                if merged_from {
+                    *operand_count.get_mut(&parent).unwrap() -= 1;
+                }
+
+                if appearance > 0 || self.words.contains_key(&parent) {
+                    let n = self.words.entry(parent.clone()).or_insert(0);
+                    *n += appearance;
+                    push_candidate(&mut candidates, &parent, *n, &merge_of, &operand_count);
+                }
+
+                // an intermediate token that nothing needs anymore
+                else if operand_count.get(&parent) == Some(&0) {
+                    if let Some(indexes) = merge_of.get(&parent) {
+                        parents.extend(self.drop_merges(indexes, 0, &mut dropped_merges));
+                    }
+                }
+            }
+        }
+
+        self.merges = self.merges.iter().enumerate().filter(
+            |(index, _)| !dropped_merges.contains(index)
+        ).map(
+            |(_, merge)| merge.clone()
+        ).collect();
+
+        PruneReport {
+            dropped,
+            compression_before,
+            compression_after: self.compression(),
+        }
+    }
+
+    // the operands of the merges, as the parents of `prune_to`
+    fn drop_merges(&self, indexes: &[usize], mut appearance: usize, dropped_merges: &mut HashSet<usize>) -> Vec<(Vec<u8>, bool, usize)> {
+        let mut result = vec![];
+
+        for index in indexes.iter() {
+            // it's already dropped with its token
+            if !dropped_merges.insert(*index) {
+                continue;
+            }
+
+            let (w1, w2) = &self.merges[*index];
+
+            result.push((w1.to_vec(), true, appearance));
+            result.push((w2.to_vec(), true, appearance));
+            appearance = 0;
+        }
+
+        result
+    }
+
+    // bytes per token
+    fn compression(&self) -> f64 {
+        let bytes = self.words.iter().map(|(word, appearance)| word.len() * appearance).sum::<usize>();
+        let tokens = self.words.values().sum::<usize>();
+
+        bytes as f64 / tokens.max(1) as f64
+    }
+}
+
+// Reverse((appearance, Reverse(rank), token)): the fewest appearances first, then the latest merge
+type Candidates = BinaryHeap<Reverse<(usize, Reverse<usize>, Vec<u8>)>>;
+
+fn push_candidate(
+    candidates: &mut Candidates,
+    token: &[u8],
+    appearance: usize,
+    merge_of: &HashMap<Vec<u8>, Vec<usize>>,
+    operand_count: &HashMap<Vec<u8>, usize>,
+) {
+    // single bytes are never dropped
+    if token.len() < 2 || operand_count.get(token).copied().unwrap_or(0) > 0 {
+        return;
+    }
+
+    let rank = merge_of.get(token).map(|indexes| indexes[0]).unwrap_or(usize::MAX);
+    candidates.push(Reverse((appearance, Reverse(rank), token.to_vec())));
+}
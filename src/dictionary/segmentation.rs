@@ -0,0 +1,201 @@
+use super::{Dictionary, TokenIds, UNKNOWN_BYTE_PENALTY};
+use crate::encoder::Encoding;
+use crate::normalizer::NormalizedString;
+use crate::unigram::{LogProbs, viterbi};
+use std::collections::HashMap;
+
+/// What `Dictionary::encode_optimal` optimizes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SegmentationObjective {
+    /// the fewest tokens
+    FewestTokens,
+
+    /// the largest sum of `ln(appearance / total appearance)`
+    LogFrequency,
+}
+
+impl Dictionary {
+    /// It builds the trie and the scores that `Segmenter` needs, so use it to encode many inputs.
+    pub fn segmenter(&self) -> Segmenter<'_> {
+        Segmenter::new(self)
+    }
+
+    /// `Segmenter::encode_longest_match`. It builds a `Segmenter` every time.
+    pub fn encode_longest_match(&self, s: &[u8]) -> Vec<Vec<u8>> {
+        self.segmenter().encode_longest_match(s)
+    }
+
+    /// `Segmenter::encode_optimal`. It builds a `Segmenter` every time.
+    pub fn encode_optimal(&self, s: &[u8], objective: SegmentationObjective) -> Vec<Vec<u8>> {
+        self.segmenter().encode_optimal(s, objective)
+    }
+
+    /// `Segmenter::tokenize_longest_match`. It builds a `Segmenter` every time.
+    pub fn tokenize_longest_match(&self, s: &[u8]) -> Encoding {
+        self.segmenter().tokenize_longest_match(s)
+    }
+
+    /// `Segmenter::tokenize_optimal`. It builds a `Segmenter` every time.
+    pub fn tokenize_optimal(&self, s: &[u8], objective: SegmentationObjective) -> Encoding {
+        self.segmenter().tokenize_optimal(s, objective)
+    }
+}
+
+/// Segmentation with the tokens of a dictionary, of any model. Unlike `Encoder`, it ignores the merges.
+pub struct Segmenter<'d> {
+    dictionary: &'d Dictionary,
+
+    ids: TokenIds,
+
+    trie: Trie,
+
+    // the length of the longest token
+    max_len: usize,
+
+    // (scores of the tokens, score of a byte that is not in the dictionary)
+    fewest_tokens: (LogProbs, f64),
+    log_frequency: (LogProbs, f64),
+}
+
+impl<'d> Segmenter<'d> {
+    pub fn new(dictionary: &'d Dictionary) -> Self {
+        let words = &dictionary.words;
+        let total = words.values().map(|appearance| (*appearance).max(1)).sum::<usize>() as f64;
+        let log_frequency = words.iter().map(
+            |(word, appearance)| (word.to_vec(), ((*appearance).max(1) as f64 / total).ln())
+        ).collect::<LogProbs>();
+        let unknown_score = log_frequency.values().fold(0.0, |a: f64, b| a.min(*b)) - UNKNOWN_BYTE_PENALTY;
+
+        Segmenter {
+            dictionary,
+            ids: dictionary.token_ids(),
+            trie: Trie::new(words.keys()),
+            max_len: words.keys().map(|word| word.len()).max().unwrap_or(1),
+            fewest_tokens: (words.keys().map(|word| (word.to_vec(), -1.0)).collect(), -1.0),
+            log_frequency: (log_frequency, unknown_score),
+        }
+    }
+
+    /// At each position, it takes the longest token of the dictionary that matches there.\
+    /// Bytes that are not in the dictionary become single-byte tokens.
+    pub fn encode_longest_match(&self, s: &[u8]) -> Vec<Vec<u8>> {
+        let normalized = self.dictionary.normalize(s).bytes;
+
+        self.longest_match(&normalized).into_iter().map(
+            |(start, end)| normalized[start..end].to_vec()
+        ).collect()
+    }
+
+    /// The best segmentation with the tokens of the dictionary, found by dynamic programming.\
+    /// Bytes that are not in the dictionary become single-byte tokens.
+    pub fn encode_optimal(&self, s: &[u8], objective: SegmentationObjective) -> Vec<Vec<u8>> {
+        let normalized = self.dictionary.normalize(s).bytes;
+
+        self.optimal(&normalized, objective).into_iter().map(
+            |(start, end)| normalized[start..end].to_vec()
+        ).collect()
+    }
+
+    /// `encode_longest_match`, with the ids and the offsets of the tokens.
+    pub fn tokenize_longest_match(&self, s: &[u8]) -> Encoding {
+        let normalized = self.dictionary.normalize(s);
+        let spans = self.longest_match(&normalized.bytes);
+
+        self.encoding(s, &normalized, &spans)
+    }
+
+    /// `encode_optimal`, with the ids and the offsets of the tokens.
+    pub fn tokenize_optimal(&self, s: &[u8], objective: SegmentationObjective) -> Encoding {
+        let normalized = self.dictionary.normalize(s);
+        let spans = self.optimal(&normalized.bytes, objective);
+
+        self.encoding(s, &normalized, &spans)
+    }
+
+    fn encoding(&self, original: &[u8], normalized: &NormalizedString, spans: &[(usize, usize)]) -> Encoding {
+        let ids = spans.iter().map(
+            |(start, end)| self.ids.id(&normalized.bytes[*start..*end]).unwrap()
+        ).collect();
+
+        Encoding::new(original, normalized, ids, &self.ids)
+    }
+
+    // (start, end) of the tokens
+    fn longest_match(&self, normalized: &[u8]) -> Vec<(usize, usize)> {
+        let mut result = vec![];
+        let mut index = 0;
+
+        while index < normalized.len() {
+            let len = self.trie.longest_prefix(&normalized[index..]).unwrap_or(1);
+            result.push((index, index + len));
+            index += len;
+        }
+
+        result
+    }
+
+    // the segmentation with the highest score, where a score is the sum of the scores of the tokens
+    fn optimal(&self, normalized: &[u8], objective: SegmentationObjective) -> Vec<(usize, usize)> {
+        let (scores, unknown_score) = match objective {
+            SegmentationObjective::FewestTokens => &self.fewest_tokens,
+            SegmentationObjective::LogFrequency => &self.log_frequency,
+        };
+
+        viterbi(normalized, scores, self.max_len, Some(*unknown_score), false)
+    }
+}
+
+// a byte trie of the tokens
+struct Trie {
+    // (children, whether a token ends here)
+    nodes: Vec<(HashMap<u8, usize>, bool)>,
+}
+
+impl Trie {
+    fn new<'a, I: Iterator<Item = &'a Vec<u8>>>(tokens: I) -> Self {
+        let mut nodes = vec![(HashMap::new(), false)];
+
+        for token in tokens {
+            let mut node = 0;
+
+            for byte in token.iter() {
+                node = match nodes[node].0.get(byte) {
+                    Some(child) => *child,
+                    None => {
+                        nodes.push((HashMap::new(), false));
+                        let child = nodes.len() - 1;
+                        nodes[node].0.insert(*byte, child);
+                        child
+                    },
+                };
+            }
+
+            nodes[node].1 = true;
+        }
+
+        Trie { nodes }
+    }
+
+    // length of the longest token that `s` starts with
+    fn longest_prefix(&self, s: &[u8]) -> Option<usize> {
+        let mut node = 0;
+        let mut result = None;
+
+        for (index, byte) in s.iter().enumerate() {
+            match self.nodes[node].0.get(byte) {
+                Some(child) => {
+                    node = *child;
+                },
+                None => {
+                    break;
+                },
+            }
+
+            if self.nodes[node].1 {
+                result = Some(index + 1);
+            }
+        }
+
+        result
+    }
+}
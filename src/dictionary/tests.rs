@@ -0,0 +1,268 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+use crate::{Normalizer, construct_dictionary};
+use crate::files::remove_file;
+use crate::test_utils::{corpus, corpus_with, temp_path};
+
+#[test]
+fn file_roundtrip_test() {
+    let bytes = corpus(b". \n\x00\xff");
+
+    for model in [Model::Bpe, Model::Unigram, Model::WordPiece] {
+        let dictionary = construct_dictionary(
+            &bytes,
+            DictionaryConfig::default()
+                .set_model(model)
+                .set_dictionary_size(300)
+                .set_normalizers(vec![Normalizer::Nfc, Normalizer::WhitespaceMarker])
+                .to_owned(),
+        );
+        let path = temp_path(&format!("bpe_rs_file_roundtrip_test_{model:?}.txt"));
+        let path = path.as_str();
+
+        dictionary.save(path).unwrap();
+        assert!(Dictionary::load(path).unwrap() == dictionary);
+        remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn prune_test() {
+    let bytes = corpus_with(|i| format!(". 가나다라 {} ", i * 7 % 13).into_bytes());
+
+    let config = DictionaryConfig::default()
+        .set_dictionary_size(400)
+        .set_keep_single_byte_tokens(false)
+        .set_character_coverage(Some(1.0))
+        .to_owned();
+    let original = construct_dictionary(&bytes, config);
+
+    for size in [original.len() - 1, original.len() - 10, 30, 0] {
+        let mut pruned = original.clone();
+        let report = pruned.prune_to(size);
+
+        assert!(pruned.len() <= size.max(pruned.iter().filter(|(word, _)| word.len() == 1).count()));
+        assert!(report.compression_after <= report.compression_before);
+        assert!(!report.dropped.is_empty());
+
+        for token in report.dropped.iter() {
+            assert!(pruned.get(token).is_none() || token.len() == 1);
+        }
+
+        // every operand of a merge is a byte, a base token or made by an earlier merge
+        let mut made = HashSet::new();
+        let merged = pruned.merges().iter().map(|(w1, w2)| [w1.as_slice(), w2.as_slice()].concat()).collect::<HashSet<_>>();
+
+        for (w1, w2) in pruned.merges().iter() {
+            for w in [w1, w2] {
+                assert!(w.len() == 1 || made.contains(w) || !merged.contains(w));
+            }
+
+            made.insert([w1.as_slice(), w2.as_slice()].concat());
+        }
+
+        // the merges of `pruned` don't make tokens that are not in `pruned`
+        for token in pruned.encoder().encode(&bytes).iter() {
+            assert!(token.len() == 1 || pruned.get(token).is_some(), "{:?}", String::from_utf8_lossy(token));
+        }
+    }
+}
+
+#[test]
+fn prune_special_cases_test() {
+    // `abc` is made by 2 merges, as `Dictionary::merge` may leave them
+    let mut dictionary = Dictionary::from_text(
+        &[
+            HEADER,
+            "word 61 10", "word 62 10", "word 63 10", "word 6162 5", "word 6263 2", "word 616263 1",
+            "merge 61 62", "merge 6162 63", "merge 62 63", "merge 61 6263",
+        ].join("\n"),
+        "",
+    ).unwrap();
+    let report = dictionary.prune_to(4);
+
+    assert_eq!(report.dropped, [b"abc".to_vec(), b"bc".to_vec()]);
+    assert_eq!(dictionary.merges(), [(b"a".to_vec(), b"b".to_vec())]);
+
+    // `[UNK]` of a WordPiece dictionary is never dropped
+    let bytes = corpus(b", jumping foxes! ");
+
+    let mut dictionary = construct_dictionary(&bytes, DictionaryConfig::default().set_model(Model::WordPiece).set_dictionary_size(300).to_owned());
+    let size = dictionary.len() - 40;
+    dictionary.prune_to(size);
+
+    assert!(dictionary.get(b"[UNK]".as_slice()).is_some());
+    assert_eq!(dictionary.tokenize(b"zzz").tokens, [b"[UNK]".to_vec()]);
+}
+
+#[test]
+fn token_ids_test() {
+    let mut bytes = corpus(". 가나다라 ".as_bytes());
+
+    let config = DictionaryConfig::default()
+        .set_dictionary_size(320)
+        .set_character_coverage(Some(1.0))
+        .to_owned();
+    let dictionary = construct_dictionary(&bytes, config);
+    let ids = dictionary.token_ids();
+
+    // dense, and every token of the dictionary has an id
+    for (id, token) in ids.iter() {
+        assert_eq!(ids.id(token), Some(id));
+    }
+
+    for (word, _) in dictionary.iter() {
+        assert!(ids.id(word).is_some());
+    }
+
+    assert_eq!(ids.token(ids.len() as u32), None);
+    assert_eq!(ids.id(b"a"), Some(b'a' as u32));
+
+    // the ids of merged tokens follow the merges, and the operands come before them
+    let mut previous = None;
+
+    for (w1, w2) in dictionary.merges().iter() {
+        let id = ids.id(&[w1.as_slice(), w2.as_slice()].concat()).unwrap();
+        assert!(previous < Some(id));
+        assert!(ids.id(w1).unwrap() < id && ids.id(w2).unwrap() < id);
+        assert_eq!(dictionary.merges()[ids.merge_rank(id).unwrap()], (w1.clone(), w2.clone()));
+        previous = Some(id);
+    }
+
+    // the same ids after a roundtrip
+    let path = temp_path("bpe_rs_token_ids_test.txt");
+    let path = path.as_str();
+    dictionary.save(path).unwrap();
+    assert_eq!(Dictionary::load(path).unwrap().token_ids(), ids);
+    remove_file(path).unwrap();
+
+    // extending a dictionary doesn't change the ids of its tokens, even the ones that no merge used
+    bytes.extend_from_slice("뷁 쀍 ".as_bytes());
+    let config = DictionaryConfig::default().set_character_coverage(Some(1.0)).to_owned();
+    let old = construct_dictionary(&bytes, config.clone().set_dictionary_size(300).to_owned());
+    let is_used = |dictionary: &Dictionary, token: &[u8]| dictionary.merges().iter().any(
+        |(w1, w2)| w1 == token || w2 == token
+    );
+    assert!(old.get("쀍".as_bytes()).is_some() && !is_used(&old, "쀍".as_bytes()));
+
+    bytes.extend_from_slice("마바사 쀍쀍 카타파하 ".repeat(32).as_bytes());
+    let extended = construct_dictionary(
+        &bytes,
+        config.clone()
+            .set_dictionary_size(400)
+            .set_initial_dictionary(Some(old.clone()))
+            .to_owned(),
+    );
+    assert!(is_used(&extended, "쀍".as_bytes()));
+
+    let old_ids = old.token_ids();
+    let extended_ids = extended.token_ids();
+
+    assert!(extended_ids.len() > old_ids.len());
+    assert!(old_ids.iter().eq(extended_ids.iter().take(old_ids.len())));
+
+    // the order is kept in the file
+    extended.save(path).unwrap();
+    assert_eq!(Dictionary::load(path).unwrap().token_ids(), extended_ids);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn segmentation_test() {
+    let words = [("a", 10), ("b", 10), ("c", 10), ("d", 10), ("ab", 50), ("bcd", 1), ("cd", 40)].into_iter().map(
+        |(word, appearance)| (word.as_bytes().to_vec(), appearance)
+    ).collect();
+    let dictionary = Dictionary::from_log_probs(words, HashMap::new());
+    let tokens = |tokens: Vec<Vec<u8>>| tokens.into_iter().map(|token| String::from_utf8(token).unwrap()).collect::<Vec<_>>();
+
+    assert_eq!(tokens(dictionary.encode_longest_match(b"abcdx")), ["ab", "cd", "x"]);
+    assert_eq!(tokens(dictionary.encode_longest_match(b"abcd")), ["ab", "cd"]);
+    assert_eq!(tokens(dictionary.encode_longest_match(b"bcda")), ["bcd", "a"]);
+    assert_eq!(tokens(dictionary.encode_optimal(b"abcd", SegmentationObjective::FewestTokens)).len(), 2);
+    assert_eq!(tokens(dictionary.encode_optimal(b"abcd", SegmentationObjective::LogFrequency)), ["ab", "cd"]);
+    assert_eq!(tokens(dictionary.encode_optimal(b"bcd", SegmentationObjective::FewestTokens)), ["bcd"]);
+    assert_eq!(tokens(dictionary.encode_optimal(b"bcd", SegmentationObjective::LogFrequency)), ["b", "cd"]);
+
+    // with a trained dictionary
+    let bytes = corpus(b". ");
+
+    let dictionary = construct_dictionary(&bytes, DictionaryConfig::default().set_dictionary_size(300).to_owned());
+    let input = b"the lazy fox jumps over the quick dog 12345.";
+    let segmenter = dictionary.segmenter();
+    let longest_match = segmenter.encode_longest_match(input);
+    let fewest_tokens = segmenter.encode_optimal(input, SegmentationObjective::FewestTokens);
+
+    for result in [&longest_match, &fewest_tokens] {
+        assert_eq!(result.concat(), input);
+    }
+
+    assert!(fewest_tokens.len() <= longest_match.len());
+    assert_eq!(longest_match, dictionary.encode_longest_match(input));
+    assert_eq!(segmenter.encode_optimal(input, SegmentationObjective::LogFrequency), dictionary.encode_optimal(input, SegmentationObjective::LogFrequency));
+}
+
+// `Regex` has a cache inside, but only its pattern is hashed
+#[allow(clippy::mutable_key_type)]
+#[test]
+fn config_eq_test() {
+    let config = DictionaryConfig::default().set_character_coverage(Some(f64::NAN)).to_owned();
+    let mut configs = HashSet::new();
+
+    configs.insert(config.clone());
+    configs.insert(config.clone());
+    configs.insert(DictionaryConfig::default());
+
+    assert_eq!(config, config.clone());
+    assert_eq!(configs.len(), 2);
+    assert_ne!(config, DictionaryConfig::default().set_minimum_gain(64, Some(0.1)).to_owned());
+
+    // patterns are compared by their source
+    let pattern = |s: &str| DictionaryConfig::default().set_forbidden_patterns(vec![regex::bytes::Regex::new(s).unwrap()]).to_owned();
+    assert_eq!(pattern("[0-9]"), pattern("[0-9]"));
+    assert_ne!(pattern("[0-9]"), pattern("[a-z]"));
+}
+
+#[test]
+fn tokenize_test() {
+    let bytes = corpus(b", jumping foxes! ");
+
+    let input = b"the lazy fox jumps over the quick dog 12345, zzz!";
+
+    for model in [Model::Bpe, Model::Unigram, Model::WordPiece] {
+        let dictionary = construct_dictionary(
+            &bytes,
+            DictionaryConfig::default()
+                .set_model(model)
+                .set_dictionary_size(300)
+                .to_owned(),
+        );
+        let ids = dictionary.token_ids();
+        let encoding = dictionary.tokenize(input);
+        let tokens = match model {
+            Model::Bpe => dictionary.encoder().encode(input),
+            Model::Unigram => dictionary.encode_viterbi(input),
+            Model::WordPiece => dictionary.encode_wordpiece(input),
+        };
+
+        assert_eq!(encoding.tokens, tokens, "{model:?}");
+
+        for ((id, token), (start, end)) in encoding.ids.iter().zip(encoding.tokens.iter()).zip(encoding.offsets.iter()) {
+            assert_eq!(ids.token(*id), Some(token.as_slice()));
+
+            // without normalizers, the offsets are the bytes of the token (`##` and `[UNK]` aside)
+            let token = token.strip_prefix(b"##").unwrap_or(token);
+            assert!(&input[*start..*end] == token || token == b"[UNK]", "{model:?}");
+        }
+    }
+
+    // `Segmenter` works with any model
+    let dictionary = construct_dictionary(&bytes, DictionaryConfig::default().set_dictionary_size(300).to_owned());
+    let segmenter = dictionary.segmenter();
+    let longest_match = segmenter.tokenize_longest_match(input);
+    let fewest_tokens = segmenter.tokenize_optimal(input, SegmentationObjective::FewestTokens);
+
+    assert_eq!(longest_match.tokens, segmenter.encode_longest_match(input));
+    assert_eq!(fewest_tokens.tokens, segmenter.encode_optimal(input, SegmentationObjective::FewestTokens));
+    assert_eq!(longest_match, dictionary.tokenize_longest_match(input));
+    assert_eq!(fewest_tokens.offsets.last().unwrap().1, input.len());
+}
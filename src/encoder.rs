@@ -0,0 +1,275 @@
+use crate::dictionary::{Dictionary, Model, TokenIds};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+mod encoding;
+mod linear;
+
+#[cfg(test)]
+mod tests;
+
+pub use encoding::Encoding;
+use linear::Linear;
+
+/// Merge-rank BPE encoder.\
+/// It applies `Dictionary::merges` in the order they were merged while training.
+pub struct Encoder<'d> {
+    dictionary: &'d Dictionary,
+
+    ids: TokenIds,
+
+    // (id1, id2) -> (rank, merged id)
+    merges: HashMap<(u32, u32), (usize, u32)>,
+
+    // multi-byte tokens that are not made by merges (e.g. characters of `character_coverage`)
+    base_tokens: HashMap<Vec<u8>, u32>,
+
+    backend: EncoderBackend,
+
+    // it's built when `EncoderBackend::Linear` is selected
+    linear: Option<Linear>,
+
+    dropout: Option<f64>,
+    rng: StdRng,
+}
+
+/// How `Encoder` finds the segmentation. Both give the same result.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EncoderBackend {
+    /// It applies the merge with the lowest rank, one by one. It can be quadratic in the worst case,
+    /// e.g. a long input without any whitespace.
+    #[default]
+    MergeRank,
+
+    /// It finds the tokens with an Aho-Corasick automaton, and checks whether adjacent tokens are
+    /// compatible. It's linear in the length of the input, but it takes a while to build the automaton.\
+    /// It doesn't support dropout: `MergeRank` is used while dropout is set.
+    Linear,
+}
+
+impl<'d> Encoder<'d> {
+    /// The dictionary has to be trained with `Model::Bpe`.
+    pub fn new(dictionary: &'d Dictionary) -> Self {
+        dictionary.assert_model(Model::Bpe);
+
+        let ids = dictionary.token_ids();
+        let mut merges = HashMap::with_capacity(dictionary.merges().len());
+        let mut base_tokens = HashMap::new();
+
+        for (rank, (w1, w2)) in dictionary.merges().iter().enumerate() {
+            let id1 = ids.id(w1).unwrap();
+            let id2 = ids.id(w2).unwrap();
+            let merged = ids.id(&[w1.as_slice(), w2.as_slice()].concat()).unwrap();
+
+            merges.entry((id1, id2)).or_insert((rank, merged));
+        }
+
+        for (id, token) in ids.iter() {
+            if token.len() > 1 && ids.merge_rank(id).is_none() {
+                base_tokens.insert(token.to_vec(), id);
+            }
+        }
+
+        Encoder {
+            dictionary,
+            ids,
+            merges,
+            base_tokens,
+            backend: EncoderBackend::MergeRank,
+            linear: None,
+            dropout: None,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    /// BPE-dropout: each applicable merge is skipped with probability `p`.\
+    /// With the same `seed`, the same sequence of calls gives the same result.
+    pub fn set_dropout(&mut self, p: Option<f64>, seed: u64) -> &mut Self {
+        if let Some(p) = p {
+            assert!((0.0..=1.0).contains(&p), "dropout probability has to be in 0.0 ~ 1.0, got {p}");
+        }
+
+        self.dropout = p;
+        self.rng = StdRng::seed_from_u64(seed);
+
+        self
+    }
+
+    pub fn set_backend(&mut self, backend: EncoderBackend) -> &mut Self {
+        if backend == EncoderBackend::Linear && self.linear.is_none() {
+            self.linear = Some(Linear::new(&self.ids, &self.merges, &self.base_tokens));
+        }
+
+        self.backend = backend;
+        self
+    }
+
+    /// It applies the normalizers of the dictionary, then the merges.
+    pub fn encode(&mut self, s: &[u8]) -> Vec<Vec<u8>> {
+        let normalized = self.dictionary.normalize(s).bytes;
+
+        self.encode_normalized(&normalized).into_iter().map(
+            |id| self.ids.token(id).unwrap().to_vec()
+        ).collect()
+    }
+
+    /// `encode`, with the ids and the offsets of the tokens.
+    pub fn tokenize(&mut self, s: &[u8]) -> Encoding {
+        let normalized = self.dictionary.normalize(s);
+        let ids = self.encode_normalized(&normalized.bytes);
+
+        Encoding::new(s, &normalized, ids, &self.ids)
+    }
+
+    /// The inputs are encoded in order, so the result is reproducible with the same seed.
+    pub fn encode_batch(&mut self, inputs: &[&[u8]]) -> Vec<Vec<Vec<u8>>> {
+        inputs.iter().map(|s| self.encode(s)).collect()
+    }
+
+    fn encode_normalized(&mut self, s: &[u8]) -> Vec<u32> {
+        if let (EncoderBackend::Linear, Some(linear), None) = (self.backend, &self.linear, self.dropout) {
+            return linear.encode(s, self.boundaries(s), &self.merges);
+        }
+
+        let mut symbols = self.initial_symbols(s);
+
+        // Reverse((rank, index of the left symbol, left id, right id))
+        let mut heap = BinaryHeap::with_capacity(symbols.len());
+        let mut skipped = vec![];
+
+        for i in 1..symbols.len() {
+            if let Some(entry) = self.merge_entry(&symbols, i - 1) {
+                heap.push(entry);
+            }
+        }
+
+        while let Some(Reverse(entry)) = heap.pop() {
+            let (_, left, left_id, right_id) = entry;
+            let right = symbols[left].next;
+
+            // the entry is outdated
+            if !symbols[left].alive || right == NONE || symbols[left].id != left_id || symbols[right].id != right_id {
+                continue;
+            }
+
+            if let Some(p) = self.dropout {
+                if self.rng.gen::<f64>() < p {
+                    skipped.push(Reverse(entry));
+                    continue;
+                }
+            }
+
+            symbols[left].id = self.merges.get(&(left_id, right_id)).unwrap().1;
+            symbols[left].next = symbols[right].next;
+            symbols[right].alive = false;
+
+            if symbols[left].next != NONE {
+                let next = symbols[left].next;
+                symbols[next].prev = left;
+
+                if let Some(entry) = self.merge_entry(&symbols, left) {
+                    heap.push(entry);
+                }
+            }
+
+            if symbols[left].prev != NONE {
+                if let Some(entry) = self.merge_entry(&symbols, symbols[left].prev) {
+                    heap.push(entry);
+                }
+            }
+
+            // skipped merges get another chance after a successful merge
+            for entry in skipped.drain(..) {
+                heap.push(entry);
+            }
+        }
+
+        symbols.iter().filter(|symbol| symbol.alive).map(|symbol| symbol.id).collect()
+    }
+
+    // characters that are base tokens, otherwise bytes
+    fn initial_symbols(&self, s: &[u8]) -> Vec<Symbol> {
+        let mut ids = Vec::with_capacity(s.len());
+
+        for chunk in s.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                let mut buffer = [0; 4];
+                let c = c.encode_utf8(&mut buffer).as_bytes();
+
+                match self.base_tokens.get(c) {
+                    Some(id) => {
+                        ids.push(*id);
+                    },
+                    None => {
+                        for byte in c.iter() {
+                            ids.push(*byte as u32);
+                        }
+                    },
+                }
+            }
+
+            for byte in chunk.invalid().iter() {
+                ids.push(*byte as u32);
+            }
+        }
+
+        let len = ids.len();
+
+        ids.into_iter().enumerate().map(
+            |(index, id)| Symbol {
+                id,
+                prev: if index == 0 { NONE } else { index - 1 },
+                next: if index + 1 == len { NONE } else { index + 1 },
+                alive: true,
+            }
+        ).collect()
+    }
+
+    // where a token can end: not in the middle of a character that's a base token
+    fn boundaries(&self, s: &[u8]) -> Vec<bool> {
+        let mut result = vec![true; s.len() + 1];
+        let mut index = 0;
+
+        for chunk in s.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                let mut buffer = [0; 4];
+
+                if self.base_tokens.contains_key(c.encode_utf8(&mut buffer).as_bytes()) {
+                    result[(index + 1)..(index + c.len_utf8())].fill(false);
+                }
+
+                index += c.len_utf8();
+            }
+
+            index += chunk.invalid().len();
+        }
+
+        result
+    }
+
+    fn merge_entry(&self, symbols: &[Symbol], left: usize) -> Option<Reverse<(usize, usize, u32, u32)>> {
+        let right = symbols[left].next;
+
+        if right == NONE {
+            return None;
+        }
+
+        let (left_id, right_id) = (symbols[left].id, symbols[right].id);
+
+        self.merges.get(&(left_id, right_id)).map(
+            |(rank, _)| Reverse((*rank, left, left_id, right_id))
+        )
+    }
+}
+
+const NONE: usize = usize::MAX;
+
+// an element of a doubly linked list
+struct Symbol {
+    id: u32,
+    prev: usize,
+    next: usize,
+    alive: bool,
+}
@@ -0,0 +1,143 @@
+use crate::dictionary::TokenIds;
+use crate::normalizer::NormalizedString;
+
+/// Result of `Encoder::tokenize` and `Dictionary::tokenize`. The vectors have the same length: one element per token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Encoding {
+    /// `Dictionary::token_ids`
+    pub ids: Vec<u32>,
+
+    /// after normalization
+    pub tokens: Vec<Vec<u8>>,
+
+    /// `(start, end)` byte range of the original input that each token came from.
+    /// A token that's only made of inserted bytes (e.g. the first `WhitespaceMarker`) gets an empty range.
+    pub offsets: Vec<(usize, usize)>,
+
+    /// `(start, end)` character range of the normalized input.
+    /// A token that's a part of a character (e.g. a single byte of it) gets the range of the whole character.
+    pub normalized_offsets: Vec<(usize, usize)>,
+
+    /// A word is a run of non-whitespace characters in the original input. It's `None` if the token
+    /// only covers whitespaces.
+    pub word_indices: Vec<Option<usize>>,
+}
+
+impl Encoding {
+    /// `ids` have to be the segmentation of `normalized`.
+    pub(crate) fn new(original: &[u8], normalized: &NormalizedString, ids: Vec<u32>, token_ids: &TokenIds) -> Self {
+        let mut spans = Vec::with_capacity(ids.len());
+        let mut start = 0;
+
+        for id in ids.iter() {
+            let end = start + token_ids.token(*id).unwrap().len();
+            spans.push((start, end));
+            start = end;
+        }
+
+        Encoding::with_spans(original, normalized, ids, &spans, token_ids)
+    }
+
+    /// `spans` are `(start, end)` byte ranges of `normalized` that the tokens cover. They don't have to be
+    /// the bytes of the tokens (e.g. `Model::WordPiece` drops whitespaces and adds `##`).
+    pub(crate) fn with_spans(
+        original: &[u8],
+        normalized: &NormalizedString,
+        ids: Vec<u32>,
+        spans: &[(usize, usize)],
+        token_ids: &TokenIds,
+    ) -> Self {
+        let tokens = ids.iter().map(|id| token_ids.token(*id).unwrap().to_vec()).collect::<Vec<_>>();
+        let chars = char_indices(&normalized.bytes);
+        let words = word_indices(original);
+
+        let mut offsets = Vec::with_capacity(ids.len());
+        let mut normalized_offsets = Vec::with_capacity(ids.len());
+        let mut word_indices = Vec::with_capacity(ids.len());
+
+        for (start, end) in spans.iter().copied() {
+
+            // inserted bytes have empty ranges
+            let ranges = normalized.alignments[start..end].iter().filter(|(s, e)| s < e);
+            let offset = match (ranges.clone().map(|(s, _)| *s).min(), ranges.map(|(_, e)| *e).max()) {
+                (Some(s), Some(e)) => (s, e),
+                _ => (normalized.alignments[start].0, normalized.alignments[start].0),
+            };
+
+            offsets.push(offset);
+            normalized_offsets.push((chars[start], chars[end - 1] + 1));
+            word_indices.push(words[offset.0..offset.1].iter().find_map(|word| *word));
+        }
+
+        Encoding {
+            ids,
+            tokens,
+            offsets,
+            normalized_offsets,
+            word_indices,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+// byte -> index of the character that it's a part of (an invalid byte is a character by itself)
+fn char_indices(bytes: &[u8]) -> Vec<usize> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            result.extend(std::iter::repeat_n(index, c.len_utf8()));
+            index += 1;
+        }
+
+        for _ in chunk.invalid().iter() {
+            result.push(index);
+            index += 1;
+        }
+    }
+
+    result
+}
+
+// byte -> index of the word that it's a part of
+fn word_indices(bytes: &[u8]) -> Vec<Option<usize>> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut words = 0;
+    let mut in_word = false;
+
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_whitespace() {
+                in_word = false;
+                result.extend(std::iter::repeat_n(None, c.len_utf8()));
+                continue;
+            }
+
+            if !in_word {
+                in_word = true;
+                words += 1;
+            }
+
+            result.extend(std::iter::repeat_n(Some(words - 1), c.len_utf8()));
+        }
+
+        for _ in chunk.invalid().iter() {
+            if !in_word {
+                in_word = true;
+                words += 1;
+            }
+
+            result.push(Some(words - 1));
+        }
+    }
+
+    result
+}
@@ -0,0 +1,210 @@
+use crate::dictionary::TokenIds;
+use aho_corasick::{AhoCorasick, Anchored, Input, MatchKind, StartKind};
+use std::collections::HashMap;
+
+/// `EncoderBackend::Linear`
+///
+/// A sequence of tokens is the merge-rank encoding of its bytes iff every token encodes to itself and
+/// every pair of adjacent tokens encodes to itself. So it takes the longest token at each position,
+/// tries shorter ones if it doesn't fit the previous token, and backtracks if none fits.
+/// A position it backtracked from is never tried again, so it's linear in the length of the input.
+pub(super) struct Linear {
+    // anchored, leftmost-longest: it finds the longest token at the beginning of the input
+    searcher: AhoCorasick,
+
+    // pattern index -> token id
+    pattern_ids: Vec<u32>,
+
+    // by token id: the merge that makes the token when the token is encoded by itself
+    // `None` for bytes and characters, and for tokens that never come out of the encoder
+    splits: Vec<Option<Split>>,
+
+    // by token id: the longest token that's a proper prefix of the token
+    next_prefix: Vec<Option<u32>>,
+
+    lengths: Vec<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Split {
+    rank: usize,
+    left: u32,
+    right: u32,
+}
+
+impl Linear {
+    pub fn new(
+        ids: &TokenIds,
+        merges: &HashMap<(u32, u32), (usize, u32)>,
+        base_tokens: &HashMap<Vec<u8>, u32>,
+    ) -> Self {
+        let mut result = Linear {
+            searcher: AhoCorasick::new::<_, &[u8]>([]).unwrap(),
+            pattern_ids: vec![],
+            splits: vec![None; ids.len()],
+            next_prefix: vec![None; ids.len()],
+            lengths: ids.iter().map(|(_, token)| token.len()).collect(),
+        };
+
+        // bytes and characters are where the encoder starts from
+        let mut reachable = vec![false; ids.len()];
+        reachable[..256].fill(true);
+
+        for (token, id) in base_tokens.iter() {
+            if std::str::from_utf8(token).map(|s| s.chars().count() == 1).unwrap_or(false) {
+                reachable[*id as usize] = true;
+            }
+        }
+
+        let mut merges_by_rank = merges.iter().map(
+            |((left, right), (rank, merged))| (*rank, *left, *right, *merged)
+        ).collect::<Vec<_>>();
+        merges_by_rank.sort();
+
+        // `(left, right)` makes `merged` if nothing between them is merged before they're made.
+        // The operands of a merge are usually made by earlier merges, so it rarely takes more than one pass.
+        loop {
+            let mut changed = false;
+
+            for (rank, left, right, merged) in merges_by_rank.iter().copied() {
+                if reachable[merged as usize] || !reachable[left as usize] || !reachable[right as usize] {
+                    continue;
+                }
+
+                if result.is_compatible(merges, left, right, false) {
+                    reachable[merged as usize] = true;
+                    result.splits[merged as usize] = Some(Split { rank, left, right });
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        let mut patterns = vec![];
+
+        for (id, token) in ids.iter() {
+            if reachable[id as usize] {
+                patterns.push(token);
+                result.pattern_ids.push(id);
+            }
+        }
+
+        result.searcher = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .start_kind(StartKind::Anchored)
+            .build(&patterns)
+            .unwrap();
+
+        for (id, token) in ids.iter() {
+            if reachable[id as usize] && token.len() > 1 {
+                result.next_prefix[id as usize] = result.longest_match(&token[..(token.len() - 1)]);
+            }
+        }
+
+        result
+    }
+
+    /// A token can only end where `boundaries` is true (e.g. not in the middle of a character that's a token).
+    pub fn encode(
+        &self,
+        s: &[u8],
+        mut boundaries: Vec<bool>,
+        merges: &HashMap<(u32, u32), (usize, u32)>,
+    ) -> Vec<u32> {
+        let mut tokens: Vec<u32> = vec![];
+        let mut pos = 0;
+        let mut candidate = self.longest_match(s);
+
+        while pos < s.len() {
+            let last = tokens.last().copied();
+            let mut token = candidate;
+
+            loop {
+                match token {
+                    Some(t) => {
+                        let end = pos + self.lengths[t as usize];
+
+                        if boundaries[end] && last.map(|last| self.is_compatible(merges, last, t, true)).unwrap_or(true) {
+                            tokens.push(t);
+                            pos = end;
+                            candidate = self.longest_match(&s[pos..]);
+                            break;
+                        }
+
+                        token = self.next_prefix[t as usize];
+                    },
+
+                    // nothing that starts here fits, so the previous token was wrong
+                    None => {
+                        boundaries[pos] = false;
+
+                        let last = tokens.pop().expect("the first token always fits");
+                        pos -= self.lengths[last as usize];
+                        candidate = self.next_prefix[last as usize];
+                        break;
+                    },
+                }
+            }
+        }
+
+        tokens
+    }
+
+    fn longest_match(&self, s: &[u8]) -> Option<u32> {
+        self.searcher.find(Input::new(s).anchored(Anchored::Yes)).map(
+            |m| self.pattern_ids[m.pattern().as_usize()]
+        )
+    }
+
+    // Whether the encoding of `left` + `right` is `[left, right]`. It undoes the merges of `left` and
+    // `right` from the latest one, and checks if the pair at the border would be merged before that.
+    // If `check_pair` is false, the pair `(left, right)` itself may be merged.
+    fn is_compatible(
+        &self,
+        merges: &HashMap<(u32, u32), (usize, u32)>,
+        mut left: u32,
+        mut right: u32,
+        check_pair: bool,
+    ) -> bool {
+        // rank of the merge that's undone last
+        let mut limit = usize::MAX;
+        let mut check_pair = check_pair;
+
+        loop {
+            if check_pair {
+                if let Some((rank, _)) = merges.get(&(left, right)) {
+                    if *rank < limit {
+                        return false;
+                    }
+                }
+            }
+
+            check_pair = true;
+
+            match (self.splits[left as usize], self.splits[right as usize]) {
+                (None, None) => {
+                    return true;
+                },
+
+                (Some(l), None) => {
+                    limit = l.rank;
+                    left = l.right;
+                },
+                (Some(l), Some(r)) if l.rank > r.rank => {
+                    limit = l.rank;
+                    left = l.right;
+                },
+
+                // with the same rank, the one on the left is merged first:
+                // it beats a merge in `left`, but loses to a merge in `right`
+                (_, Some(r)) => {
+                    limit = r.rank + 1;
+                    right = r.left;
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use super::*;
+use crate::{DictionaryConfig, construct_dictionary};
+use crate::test_utils::corpus;
+use std::collections::HashMap;
+
+fn sample_corpus() -> Vec<u8> {
+    corpus(". 다람쥐 헌 쳇바퀴에 타고파. ".as_bytes())
+}
+
+// encoding the training corpus gives the same segmentation as the training
+#[test]
+fn merge_rank_test() {
+    let bytes = sample_corpus();
+
+    for coverage in [None, Some(1.0)] {
+        let dictionary = construct_dictionary(
+            &bytes,
+            DictionaryConfig::default()
+                .set_dictionary_size(400)
+                .set_character_coverage(coverage)
+                .to_owned(),
+        );
+        let tokens = dictionary.encoder().encode(&bytes);
+        let mut counts = HashMap::new();
+
+        for token in tokens.iter() {
+            *counts.entry(token.to_vec()).or_insert(0) += 1;
+        }
+
+        assert_eq!(tokens.concat(), bytes);
+
+        for (word, appearance) in dictionary.iter() {
+            assert_eq!(counts.get(word).copied().unwrap_or(0), *appearance, "{:?} {coverage:?}", String::from_utf8_lossy(word));
+        }
+    }
+}
+
+#[test]
+fn dropout_test() {
+    let bytes = sample_corpus();
+    let dictionary = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_dictionary_size(400)
+            .to_owned(),
+    );
+    let input = b"the lazy fox jumps over the quick dog.";
+    let no_dropout = dictionary.encoder().encode(input);
+
+    assert_eq!(dictionary.encoder().set_dropout(Some(0.0), 7).encode(input), no_dropout);
+    assert_eq!(dictionary.encoder().set_dropout(Some(1.0), 7).encode(input).len(), input.len());
+
+    let batch = vec![&input[..]; 8];
+    let result1 = dictionary.encoder().set_dropout(Some(0.3), 7).encode_batch(&batch);
+    let result2 = dictionary.encoder().set_dropout(Some(0.3), 7).encode_batch(&batch);
+
+    // reproducible with the same seed
+    assert_eq!(result1, result2);
+
+    for tokens in result1.iter() {
+        assert_eq!(tokens.concat(), input);
+        assert!(tokens.len() >= no_dropout.len());
+    }
+
+    // dropout makes different segmentations
+    assert!(result1.iter().any(|tokens| *tokens != no_dropout));
+}
+
+// the linear backend gives exactly the same segmentation as the merge-rank one
+#[test]
+fn linear_backend_test() {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    let bytes = sample_corpus();
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut inputs = vec![
+        bytes.clone(),
+        b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+        b"thethethethe quickquick    foxfox".to_vec(),
+        "다람쥐다람쥐 쳇바퀴\u{ff}타고 \u{1f600}".as_bytes().to_vec(),
+        vec![],
+    ];
+
+    // random strings of the letters in the corpus, and random bytes
+    for _ in 0..32 {
+        let alphabet = b"the quick brown fox jumps over lazy dog.0123";
+        inputs.push((0..rng.gen_range(1..200)).map(|_| alphabet[rng.gen_range(0..alphabet.len())]).collect());
+        inputs.push((0..rng.gen_range(1..50)).map(|_| rng.gen::<u8>()).collect());
+    }
+
+    for coverage in [None, Some(1.0)] {
+        let mut dictionary = construct_dictionary(
+            &bytes,
+            DictionaryConfig::default()
+                .set_dictionary_size(400)
+                .set_character_coverage(coverage)
+                .to_owned(),
+        );
+
+        // merges of another dictionary come after the ones of `dictionary`
+        if coverage.is_some() {
+            let other = construct_dictionary(
+                &b"aaaa abab abba baab ".repeat(32),
+                DictionaryConfig::default().set_dictionary_size(280).to_owned(),
+            );
+            dictionary.merge(&other);
+        }
+
+        let mut merge_rank = dictionary.encoder();
+        let mut linear = dictionary.encoder();
+        linear.set_backend(EncoderBackend::Linear);
+
+        for input in inputs.iter() {
+            assert_eq!(linear.encode(input), merge_rank.encode(input), "{:?} {coverage:?}", String::from_utf8_lossy(input));
+        }
+
+        // dropout falls back to the merge-rank backend
+        linear.set_dropout(Some(1.0), 0);
+        assert_eq!(linear.encode(b"the lazy dog").len(), 12);
+    }
+}
+
+#[test]
+fn tokenize_test() {
+    use crate::Normalizer;
+
+    let bytes = sample_corpus();
+    let dictionary = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_dictionary_size(400)
+            .set_normalizers(vec![Normalizer::Lowercase, Normalizer::CollapseWhitespace, Normalizer::WhitespaceMarker])
+            .to_owned(),
+    );
+    let input = "The  LAZY fox 다람쥐 e\u{301}".as_bytes();
+    let encoding = dictionary.tokenize(input);
+    let ids = dictionary.token_ids();
+
+    assert_eq!(encoding.tokens, dictionary.encoder().encode(input));
+    assert_eq!(encoding.tokens.concat(), dictionary.normalize(input).bytes);
+
+    for (id, token) in encoding.ids.iter().zip(encoding.tokens.iter()) {
+        assert_eq!(ids.token(*id), Some(token.as_slice()));
+    }
+
+    // the marker at the beginning is inserted
+    assert_eq!(encoding.offsets[0].0, 0);
+
+    for (token, (start, end)) in encoding.tokens.iter().zip(encoding.offsets.iter()) {
+        let original = String::from_utf8_lossy(&input[*start..*end]).to_lowercase();
+        let token = String::from_utf8_lossy(token).replace('\u{2581}', " ");
+
+        // a token may be a part of a character
+        if !token.contains('\u{fffd}') {
+            assert_eq!(original.trim(), token.trim(), "{:?}", encoding);
+        }
+    }
+
+    // the offsets cover the input in order
+    assert!(encoding.offsets.windows(2).all(|w| w[0].1 <= w[1].0 || w[0] == w[1]));
+    assert_eq!(encoding.offsets.last().unwrap().1, input.len());
+    assert_eq!(encoding.normalized_offsets.last().unwrap().1, String::from_utf8_lossy(&dictionary.normalize(input).bytes).chars().count());
+
+    let words = encoding.tokens.iter().zip(encoding.word_indices.iter()).filter(
+        |(token, _)| String::from_utf8_lossy(token).contains("lazy")
+    ).map(|(_, word)| *word).collect::<Vec<_>>();
+    assert_eq!(words, vec![Some(1)]);
+    assert_eq!(*encoding.word_indices.last().unwrap(), Some(4));
+}
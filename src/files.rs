@@ -55,6 +55,21 @@ pub fn read_string(path: &str) -> Result<String, FileError> {
     }
 }
 
+/// It reads `path` by `chunk_size` bytes, so that the file doesn't have to fit in memory.
+pub fn for_each_chunk<F: FnMut(&[u8])>(path: &str, chunk_size: usize, mut f: F) -> Result<(), FileError> {
+    let mut file = File::open(path).map_err(|e| FileError::from_std(e, path))?;
+    let mut buffer = vec![0; chunk_size.max(1)];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => { return Ok(()); },
+            Ok(n) => { f(&buffer[..n]); },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => { return Err(FileError::from_std(e, path)); },
+        }
+    }
+}
+
 pub fn write_bytes(path: &str, bytes: &[u8], write_mode: WriteMode) -> Result<(), FileError> {
     let option: OpenOptions = write_mode.into();
 
@@ -219,6 +234,11 @@ pub fn read_dir(path: &str) -> Result<Vec<String>, FileError> {
     }
 }
 
+/// It overwrites `to` if it exists.
+pub fn rename(from: &str, to: &str) -> Result<(), FileError> {
+    fs::rename(from, to).map_err(|e| FileError::from_std(e, from))
+}
+
 pub fn remove_file(path: &str) -> Result<(), FileError> {
     fs::remove_file(path).map_err(|e| FileError::from_std(e, path))
 }
@@ -288,7 +308,7 @@ impl FileError {
     }
 
     pub fn render_error(&self) -> String {
-        let path = self.given_path.as_ref().map(|p| p.to_string()).unwrap_or(String::new());
+        let path = self.given_path.as_ref().map(|p| p.to_string()).unwrap_or_default();
 
         match &self.kind {
             FileErrorKind::FileNotFound => format!(
@@ -1,9 +1,21 @@
 mod bpe;
+mod checkpoint;
+mod decoder;
 mod dictionary;
+mod encoder;
 pub mod files;
 mod log;
 mod multi;
+mod normalizer;
+mod unigram;
+mod wordpiece;
 mod utils;
 
-pub use bpe::{construct_dictionary, construct_dictionary_from_dir};
-pub use dictionary::{Dictionary, DictionaryConfig};
+#[cfg(test)]
+mod test_utils;
+
+pub use bpe::{Merge, MergeCandidate, MergeScorer, Observer, Scoring, StopReason, Trainer, construct_dictionary, construct_dictionary_from_dir, construct_dictionary_from_files, construct_dictionary_with_snapshots};
+pub use dictionary::{Dictionary, DictionaryConfig, Model, PruneReport, SegmentationObjective, Segmenter, TokenIds};
+pub use decoder::Decoder;
+pub use encoder::{Encoder, EncoderBackend, Encoding};
+pub use normalizer::{NormalizedString, Normalizer, WHITESPACE_MARKER};
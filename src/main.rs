@@ -2,7 +2,7 @@ use bpe_rs::{DictionaryConfig, construct_dictionary_from_dir};
 
 // TEST
 fn main() {
-    let dict = construct_dictionary_from_dir(
+    let _dict = construct_dictionary_from_dir(
         DictionaryConfig::default()
             .set_dictionary_size(16384)
             .set_dir("./corpus/wikipedia".to_string())
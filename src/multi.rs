@@ -1,5 +1,6 @@
-use crate::{Dictionary, DictionaryConfig, construct_dictionary};
-use crate::files::merge_files;
+use crate::{Dictionary, DictionaryConfig};
+use crate::bpe::{construct_dictionary_with_snapshots, construct_dictionary_with_snapshots_from_files, worker_checkpoint_path};
+use crate::files::{FileError, merge_files};
 use crate::log::write_log;
 use crate::utils::prettify_file_size;
 use std::sync::mpsc;
@@ -7,11 +8,16 @@ use std::thread::{self, sleep};
 use std::time::Duration;
 
 pub enum MessageFromMain {
-    ReadTheseFiles(Vec<String>),
+    // (index of the chunk, files)
+    ReadTheseFiles(usize, Vec<String>),
 }
 
 pub enum MessageToMain {
-    NewDictionary(Dictionary),
+    // (index of the chunk, dictionary, (size, snapshot) of the chunk)
+    NewDictionary(usize, Box<Dictionary>, Vec<(usize, Dictionary)>),
+
+    // (index of the chunk, error)
+    Error(usize, FileError),
     Done,
 }
 
@@ -28,10 +34,6 @@ impl Channel {
     pub fn try_recv(&self) -> Result<MessageToMain, mpsc::TryRecvError> {
         self.rx_to_main.try_recv()
     }
-
-    pub fn block_recv(&self) -> Result<MessageToMain, mpsc::RecvError> {
-        self.rx_to_main.recv()
-    }
 }
 
 pub fn init_channels(n: usize, config: &DictionaryConfig) -> Vec<Channel> {
@@ -52,17 +54,6 @@ pub fn init_channel(config: &DictionaryConfig) -> Channel {
     }
 }
 
-pub fn distribute_messages(
-    messages: Vec<MessageFromMain>,
-    channels: &[Channel],
-) -> Result<(), mpsc::SendError<MessageFromMain>> {
-    for (index, message) in messages.into_iter().enumerate() {
-        channels[index % channels.len()].send(message)?;
-    }
-
-    Ok(())
-}
-
 pub fn event_loop(
     tx_to_main: mpsc::Sender<MessageToMain>,
     rx_from_main: mpsc::Receiver<MessageFromMain>,
@@ -84,40 +75,81 @@ pub fn event_loop(
 
         while let Ok(msg) = rx_from_main.try_recv() {
             match msg {
-                MessageFromMain::ReadTheseFiles(files) => {
-                    queue.push(files);
+                MessageFromMain::ReadTheseFiles(chunk_index, files) => {
+                    queue.push((chunk_index, files));
                     got_nothing = 0;
                 },
             }
         }
 
-        while let Some(files) = queue.pop() {
-            let files_len = files.len();
-            let bytes = merge_files(files, config.dir_option.file_separator);
-            write_log(
-                config.write_log_at.clone(),
-                &worker_id,
-                &format!(
-                    "registered {files_len} files (total size {})",
-                    prettify_file_size(bytes.len() as u64),
-                ),
-            );
+        while let Some((chunk_index, files)) = queue.pop() {
+            let mut chunk_config = config.clone();
+
+            // each chunk has its own checkpoint
+            if let Some(path) = &config.checkpoint_at {
+                chunk_config.checkpoint_at = Some(worker_checkpoint_path(path, chunk_index));
+            }
+
+            // snapshots are merged and saved by the master
+            let (new_dictionary, snapshots) = match &config.disk_backed_at {
+                // the files are not loaded in memory
+                Some(path) => {
+                    chunk_config.disk_backed_at = Some(format!("{path}.{chunk_index}"));
+                    write_log(
+                        config.write_log_at.clone(),
+                        &worker_id,
+                        &format!("registered {} files (disk-backed)", files.len()),
+                    );
+
+                    match construct_dictionary_with_snapshots_from_files(&files, chunk_config) {
+                        Ok(result) => result,
+                        Err(e) => {
+                            write_log(
+                                config.write_log_at.clone(),
+                                &worker_id,
+                                &format!("failed to construct a dictionary of chunk {chunk_index}: {e}"),
+                            );
+
+                            // the master stops at the error, so it's the last message
+                            let _ = tx_to_main.send(MessageToMain::Error(chunk_index, e));
+                            return;
+                        },
+                    }
+                },
+                None => {
+                    let files_len = files.len();
+                    let bytes = merge_files(files, config.dir_option.file_separator);
+                    write_log(
+                        config.write_log_at.clone(),
+                        &worker_id,
+                        &format!(
+                            "registered {files_len} files (total size {})",
+                            prettify_file_size(bytes.len() as u64),
+                        ),
+                    );
+
+                    construct_dictionary_with_snapshots(&bytes, chunk_config)
+                },
+            };
 
-            let new_dictionary = construct_dictionary(&bytes, config.clone());
             write_log(
                 config.write_log_at.clone(),
                 &worker_id,
                 &format!("constructed dictionary with {} words", new_dictionary.len()),
             );
 
-            tx_to_main.send(MessageToMain::NewDictionary(new_dictionary)).unwrap();
+            // the master is gone if another chunk has failed
+            if tx_to_main.send(MessageToMain::NewDictionary(chunk_index, Box::new(new_dictionary), snapshots)).is_err() {
+                return;
+            }
+
             got_nothing = 0;
         }
 
         sleep(Duration::from_millis(1000));
 
         if got_nothing > 5 {
-            tx_to_main.send(MessageToMain::Done).unwrap();
+            let _ = tx_to_main.send(MessageToMain::Done);
             write_log(
                 config.write_log_at.clone(),
                 &worker_id,
@@ -0,0 +1,297 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::{canonical_combining_class, is_combining_mark};
+
+#[cfg(test)]
+mod tests;
+
+/// A normalizer is applied to the input before training and encoding.\
+/// Multiple normalizers can be composed: they're applied in the given order.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Normalizer {
+    Nfc,
+    Nfkc,
+    Lowercase,
+
+    /// `é` -> `e`
+    StripAccents,
+
+    /// A run of whitespaces becomes a single space.
+    CollapseWhitespace,
+
+    /// Control characters (except `\t`, `\n` and `\r`) become spaces.
+    ReplaceControlCharacters,
+
+    /// Spaces become `WHITESPACE_MARKER` (`▁`), and the marker is prepended to the input, like SentencePiece.
+    /// Multi-byte tokens can have the marker only at the beginning, so word-initial tokens are explicit.\
+    /// `denormalize` restores the spaces. It's lossless unless the input already has the marker.
+    WhitespaceMarker,
+}
+
+pub const WHITESPACE_MARKER: char = '\u{2581}';
+
+pub struct NormalizedString {
+    pub bytes: Vec<u8>,
+
+    /// `alignments[i]` is the range of the original input that `bytes[i]` came from.
+    pub alignments: Vec<(usize, usize)>,
+}
+
+impl NormalizedString {
+    /// It doesn't change anything.
+    pub fn identity(bytes: &[u8]) -> Self {
+        NormalizedString {
+            bytes: bytes.to_vec(),
+            alignments: (0..bytes.len()).map(|i| (i, i + 1)).collect(),
+        }
+    }
+}
+
+// (character, range in the original input)
+type AlignedChars = Vec<(char, (usize, usize))>;
+
+enum Segment {
+    Valid(AlignedChars),
+
+    // invalid UTF-8 sequences are not normalized
+    Invalid(u8, usize),
+}
+
+pub fn normalize(bytes: &[u8], normalizers: &[Normalizer]) -> NormalizedString {
+    if normalizers.is_empty() {
+        return NormalizedString::identity(bytes);
+    }
+
+    let mut segments = vec![];
+    let mut index = 0;
+
+    for chunk in bytes.utf8_chunks() {
+        let mut chars = Vec::with_capacity(chunk.valid().len());
+
+        for c in chunk.valid().chars() {
+            chars.push((c, (index, index + c.len_utf8())));
+            index += c.len_utf8();
+        }
+
+        segments.push(Segment::Valid(chars));
+
+        for byte in chunk.invalid().iter() {
+            segments.push(Segment::Invalid(*byte, index));
+            index += 1;
+        }
+    }
+
+    let mut result = NormalizedString {
+        bytes: Vec::with_capacity(bytes.len()),
+        alignments: Vec::with_capacity(bytes.len()),
+    };
+
+    if normalizers.contains(&Normalizer::WhitespaceMarker) {
+        for byte in WHITESPACE_MARKER.to_string().as_bytes() {
+            result.bytes.push(*byte);
+            result.alignments.push((0, 0));
+        }
+    }
+
+    for segment in segments.into_iter() {
+        match segment {
+            Segment::Valid(mut chars) => {
+                for normalizer in normalizers.iter() {
+                    chars = normalizer.apply(chars);
+                }
+
+                let mut buffer = [0; 4];
+
+                for (c, range) in chars.iter() {
+                    for byte in c.encode_utf8(&mut buffer).as_bytes() {
+                        result.bytes.push(*byte);
+                        result.alignments.push(*range);
+                    }
+                }
+            },
+            Segment::Invalid(byte, index) => {
+                result.bytes.push(byte);
+                result.alignments.push((index, index + 1));
+            },
+        }
+    }
+
+    result
+}
+
+/// It undoes the reversible normalizers (for now, only `WhitespaceMarker`).
+pub fn denormalize(bytes: &[u8], normalizers: &[Normalizer]) -> Vec<u8> {
+    if !normalizers.contains(&Normalizer::WhitespaceMarker) {
+        return bytes.to_vec();
+    }
+
+    let marker = WHITESPACE_MARKER.to_string();
+    let marker = marker.as_bytes();
+    let bytes = bytes.strip_prefix(marker).unwrap_or(bytes);
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index..].starts_with(marker) {
+            result.push(b' ');
+            index += marker.len();
+        }
+
+        else {
+            result.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    result
+}
+
+/// Whether the token has `WHITESPACE_MARKER` only at the beginning.\
+/// It also rejects tokens that end with a part of the marker, since they would have the marker in the middle later.
+pub fn marker_only_at_start(token: &[u8]) -> bool {
+    let marker = WHITESPACE_MARKER.to_string();
+    let marker = marker.as_bytes();
+    let mut rest = token;
+
+    while let Some(r) = rest.strip_prefix(marker) {
+        rest = r;
+    }
+
+    if rest.windows(marker.len()).any(|w| w == marker) {
+        return false;
+    }
+
+    for i in 1..marker.len() {
+        if rest.len() > i && rest.ends_with(&marker[..i]) {
+            return false;
+        }
+    }
+
+    true
+}
+
+impl Normalizer {
+    fn apply(&self, chars: AlignedChars) -> AlignedChars {
+        let mut result = Vec::with_capacity(chars.len());
+
+        match self {
+            Normalizer::Nfc | Normalizer::Nfkc => {
+                // A base character and the following combining marks are normalized together,
+                // and every character in the result is aligned to the whole cluster.
+                let mut cluster = String::new();
+                let mut cluster_range = (0, 0);
+
+                for (c, range) in chars.iter() {
+                    if !cluster.is_empty() && !continues_cluster(*c) {
+                        push_normalized_cluster(&cluster, cluster_range, *self, &mut result);
+                        cluster.clear();
+                    }
+
+                    if cluster.is_empty() {
+                        cluster_range = *range;
+                    }
+
+                    cluster.push(*c);
+                    cluster_range.1 = range.1;
+                }
+
+                if !cluster.is_empty() {
+                    push_normalized_cluster(&cluster, cluster_range, *self, &mut result);
+                }
+            },
+            Normalizer::Lowercase => {
+                for (c, range) in chars.iter() {
+                    for lower in c.to_lowercase() {
+                        result.push((lower, *range));
+                    }
+                }
+            },
+            Normalizer::StripAccents => {
+                for (c, range) in chars.iter() {
+                    let decomposed = c.to_string().nfd().collect::<String>();
+
+                    if decomposed.chars().any(is_combining_mark) {
+                        for stripped in decomposed.chars().filter(|c| !is_combining_mark(*c)).nfc() {
+                            result.push((stripped, *range));
+                        }
+                    }
+
+                    else {
+                        result.push((*c, *range));
+                    }
+                }
+            },
+            Normalizer::CollapseWhitespace => {
+                for (c, range) in chars.iter() {
+                    if c.is_whitespace() {
+                        match result.last_mut() {
+                            Some((' ', prev_range)) if prev_range.1 == range.0 => {
+                                prev_range.1 = range.1;
+                            },
+                            _ => {
+                                result.push((' ', *range));
+                            },
+                        }
+                    }
+
+                    else {
+                        result.push((*c, *range));
+                    }
+                }
+            },
+            Normalizer::ReplaceControlCharacters => {
+                for (c, range) in chars.iter() {
+                    if c.is_control() && !['\t', '\n', '\r'].contains(c) {
+                        result.push((' ', *range));
+                    }
+
+                    else {
+                        result.push((*c, *range));
+                    }
+                }
+            },
+            Normalizer::WhitespaceMarker => {
+                for (c, range) in chars.iter() {
+                    if *c == ' ' {
+                        result.push((WHITESPACE_MARKER, *range));
+                    }
+
+                    else {
+                        result.push((*c, *range));
+                    }
+                }
+            },
+        }
+
+        result
+    }
+}
+
+// whether `c` can be composed with the previous character
+fn continues_cluster(c: char) -> bool {
+    canonical_combining_class(c) != 0
+    || is_combining_mark(c)
+
+    // hangul medial vowels and final consonants
+    || ('\u{1160}'..='\u{11ff}').contains(&c)
+}
+
+fn push_normalized_cluster(
+    cluster: &str,
+    range: (usize, usize),
+    normalizer: Normalizer,
+    result: &mut AlignedChars,
+) {
+    match normalizer {
+        Normalizer::Nfc => {
+            for c in cluster.nfc() {
+                result.push((c, range));
+            }
+        },
+        Normalizer::Nfkc => {
+            for c in cluster.nfkc() {
+                result.push((c, range));
+            }
+        },
+        _ => unreachable!(),
+    }
+}
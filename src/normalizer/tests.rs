@@ -0,0 +1,66 @@
+use super::*;
+
+#[test]
+fn normalizer_test() {
+    let samples = vec![
+        ("e\u{301}cole", vec![Normalizer::Nfc], "\u{e9}cole"),
+        ("\u{fb01}ve \u{2460}", vec![Normalizer::Nfkc], "five 1"),
+        ("\u{1100}\u{1161}", vec![Normalizer::Nfc], "가"),
+        ("ÉCOLE", vec![Normalizer::Lowercase], "école"),
+        ("café naïve 가", vec![Normalizer::StripAccents], "cafe naive 가"),
+        ("a  \t\n b", vec![Normalizer::CollapseWhitespace], "a b"),
+        ("a\u{0}b\nc", vec![Normalizer::ReplaceControlCharacters], "a b\nc"),
+        ("  CAFÉ  ", vec![Normalizer::Nfkc, Normalizer::Lowercase, Normalizer::StripAccents, Normalizer::CollapseWhitespace], " cafe "),
+    ];
+
+    for (s, normalizers, answer) in samples.into_iter() {
+        assert_eq!(
+            String::from_utf8_lossy(&normalize(s.as_bytes(), &normalizers).bytes),
+            answer,
+        );
+    }
+}
+
+#[test]
+fn alignment_test() {
+    let s = "e\u{301}  X\u{ff}";
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0xff);  // invalid UTF-8
+
+    let result = normalize(&bytes, &[Normalizer::Nfc, Normalizer::CollapseWhitespace, Normalizer::Lowercase]);
+
+    assert_eq!(result.bytes, ["\u{e9} x\u{ff}".as_bytes(), &[0xff]].concat());
+    assert_eq!(result.alignments.len(), result.bytes.len());
+
+    // `é` came from `e` + U+0301
+    assert_eq!(result.alignments[0], (0, 3));
+    assert_eq!(result.alignments[1], (0, 3));
+
+    // the two spaces are collapsed
+    assert_eq!(result.alignments[2], (3, 5));
+    assert_eq!(result.alignments[3], (5, 6));
+
+    // the invalid byte is kept as it is
+    assert_eq!(*result.alignments.last().unwrap(), (8, 9));
+}
+
+#[test]
+fn whitespace_marker_test() {
+    let samples = vec![
+        ("Hello world", "▁Hello▁world"),
+        (" two  spaces ", "▁▁two▁▁spaces▁"),
+    ];
+
+    for (s, answer) in samples.into_iter() {
+        let normalized = normalize(s.as_bytes(), &[Normalizer::WhitespaceMarker]);
+
+        assert_eq!(String::from_utf8_lossy(&normalized.bytes), answer);
+        assert_eq!(denormalize(&normalized.bytes, &[Normalizer::WhitespaceMarker]), s.as_bytes());
+    }
+
+    assert!(marker_only_at_start("▁the".as_bytes()));
+    assert!(marker_only_at_start("▁▁".as_bytes()));
+    assert!(marker_only_at_start(&"▁".as_bytes()[..2]));
+    assert!(!marker_only_at_start("e▁t".as_bytes()));
+    assert!(!marker_only_at_start(&"e▁".as_bytes()[..2]));
+}
@@ -0,0 +1,23 @@
+// helpers that the tests of the modules share
+
+/// 64 lines of `the quick brown fox {i} jumps over the lazy dog`, each followed by `tail`
+pub fn corpus(tail: &[u8]) -> Vec<u8> {
+    corpus_with(|_| tail.to_vec())
+}
+
+/// `corpus`, but the tail depends on the number of the line
+pub fn corpus_with(tail: impl Fn(usize) -> Vec<u8>) -> Vec<u8> {
+    let mut result = vec![];
+
+    for i in 0..64 {
+        result.extend_from_slice(format!("the quick brown fox {i} jumps over the lazy dog").as_bytes());
+        result.extend_from_slice(&tail(i));
+    }
+
+    result
+}
+
+/// `name` in the temporary directory
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir().join(name).to_str().unwrap().to_string()
+}
@@ -0,0 +1,417 @@
+use crate::dictionary::{Dictionary, DictionaryConfig};
+use crate::log::write_log;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(test)]
+mod tests;
+
+/// Pieces longer than this (in bytes) are not considered, unless `maximum_token_length` is set.
+pub const DEFAULT_MAXIMUM_PIECE_LENGTH: usize = 16;
+
+/// The seed vocabulary is this times larger than `dictionary_size`.
+pub const SEED_VOCABULARY_RATIO: usize = 8;
+
+/// In each pruning round, it keeps this ratio of the vocabulary.
+pub const SHRINKING_FACTOR: f64 = 0.75;
+
+/// EM iterations per pruning round
+pub const EM_ITERATIONS: usize = 2;
+
+/// Pieces with smaller expected count are removed at the M step, unless they're required.
+pub const MINIMUM_EXPECTED_COUNT: f64 = 0.5;
+
+// <piece, log probability>
+pub type LogProbs = HashMap<Vec<u8>, f64>;
+
+/// SentencePiece-style Unigram language model.\
+/// `bytes` has to be normalized already. It's called by `construct_dictionary` when `config.model` is `Model::Unigram`.
+pub(crate) fn construct_unigram_dictionary(bytes: &[u8], config: &DictionaryConfig) -> Dictionary {
+    let words = count_words(bytes, config.ultimate_separator);
+    let max_len = config.maximum_token_length.unwrap_or(DEFAULT_MAXIMUM_PIECE_LENGTH);
+
+    let required = required_pieces(&words, config);
+
+    let mut log_probs = seed_pieces(&words, &required, config, max_len);
+
+    loop {
+        for _ in 0..EM_ITERATIONS {
+            let counts = expected_counts(&words, &log_probs, max_len);
+            log_probs = maximize(&counts, &required);
+        }
+
+        if log_probs.len() <= config.dictionary_size {
+            break;
+        }
+
+        let target = ((log_probs.len() as f64 * SHRINKING_FACTOR) as usize).max(config.dictionary_size);
+        log_probs = prune(&words, &log_probs, &required, target, max_len);
+
+        write_log(
+            config.write_log_at.clone(),
+            "unigram",
+            &format!("pruned the vocabulary: {} pieces", log_probs.len()),
+        );
+
+        // there are too many required pieces
+        if log_probs.len() > target {
+            break;
+        }
+    }
+
+    if log_probs.len() > config.dictionary_size {
+        write_log(
+            config.write_log_at.clone(),
+            "unigram",
+            &format!(
+                "the vocabulary ({} pieces) is larger than dictionary_size ({}): {} pieces are required",
+                log_probs.len(),
+                config.dictionary_size,
+                required.len(),
+            ),
+        );
+    }
+
+    let mut appearances = log_probs.keys().map(|piece| (piece.to_vec(), 0)).collect::<HashMap<_, _>>();
+
+    for (word, count) in words.iter() {
+        for (start, end) in viterbi(word, &log_probs, max_len, None, false) {
+            *appearances.get_mut(&word[start..end]).unwrap() += *count;
+        }
+    }
+
+    Dictionary::from_log_probs(appearances, log_probs)
+}
+
+// Single characters (and single bytes) are never pruned, so that every word can be segmented.
+// They count toward `dictionary_size`: if there are too many, the rarest characters fall back to bytes,
+// as long as the bytes are kept (`keep_single_byte_tokens`).
+fn required_pieces(words: &HashMap<Vec<u8>, usize>, config: &DictionaryConfig) -> HashSet<Vec<u8>> {
+    let mut result = HashSet::new();
+    let mut char_counts = HashMap::new();
+
+    for (word, count) in words.iter() {
+        for chunk in word.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                *char_counts.entry(c.to_string().into_bytes()).or_insert(0) += *count;
+            }
+
+            for byte in chunk.invalid().iter() {
+                result.insert(vec![*byte]);
+            }
+        }
+    }
+
+    if config.keep_single_byte_tokens {
+        for byte in 0..=255 {
+            result.insert(vec![byte]);
+        }
+    }
+
+    let mut char_counts = char_counts.into_iter().filter(
+        |(c, _)| !result.contains(c)
+    ).collect::<Vec<_>>();
+
+    // the order has to be deterministic
+    char_counts.sort_by(|(c1, n1), (c2, n2)| n2.cmp(n1).then_with(|| c1.cmp(c2)));
+
+    if config.keep_single_byte_tokens {
+        char_counts.truncate(config.dictionary_size.saturating_sub(result.len()));
+    }
+
+    result.extend(char_counts.into_iter().map(|(c, _)| c));
+    result
+}
+
+/// Unigram doesn't make pieces across words.
+/// A word is a run of whitespaces followed by a run of non-whitespaces. `ultimate_separator` is a word by itself.
+pub fn count_words(bytes: &[u8], ultimate_separator: Option<u8>) -> HashMap<Vec<u8>, usize> {
+    let mut result = HashMap::new();
+    let mut start = 0;
+
+    for i in 0..bytes.len() {
+        let is_boundary = i > start && (
+            Some(bytes[i]) == ultimate_separator
+            || Some(bytes[i - 1]) == ultimate_separator
+            || (is_whitespace_start(&bytes[i..]) && !is_whitespace_end(&bytes[..i]))
+            || i - start >= 4 * DEFAULT_MAXIMUM_PIECE_LENGTH && !is_continuation_byte(bytes[i])
+        );
+
+        if is_boundary {
+            *result.entry(bytes[start..i].to_vec()).or_insert(0) += 1;
+            start = i;
+        }
+    }
+
+    if start < bytes.len() {
+        *result.entry(bytes[start..].to_vec()).or_insert(0) += 1;
+    }
+
+    result
+}
+
+// `▁` of `Normalizer::WhitespaceMarker` is also a whitespace
+fn is_whitespace_start(bytes: &[u8]) -> bool {
+    bytes[0].is_ascii_whitespace() || bytes.starts_with("\u{2581}".as_bytes())
+}
+
+fn is_whitespace_end(bytes: &[u8]) -> bool {
+    bytes[bytes.len() - 1].is_ascii_whitespace() || bytes.ends_with("\u{2581}".as_bytes())
+}
+
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+// substrings of the words that end at character boundaries, scored by `frequency * length`
+fn seed_pieces(
+    words: &HashMap<Vec<u8>, usize>,
+    required: &HashSet<Vec<u8>>,
+    config: &DictionaryConfig,
+    max_len: usize,
+) -> LogProbs {
+    let mut substrings = HashMap::new();
+
+    for (word, count) in words.iter() {
+        let boundaries = (0..=word.len()).filter(
+            |i| *i == word.len() || !is_continuation_byte(word[*i])
+        ).collect::<Vec<_>>();
+
+        for (index, start) in boundaries.iter().enumerate() {
+            for end in boundaries[(index + 2).min(boundaries.len())..].iter() {
+                if end - start > max_len {
+                    break;
+                }
+
+                *substrings.entry(&word[*start..*end]).or_insert(0) += *count;
+            }
+        }
+    }
+
+    let mut substrings = substrings.into_iter().filter(
+        |(piece, count)| *count >= config.minimum_appearance.unwrap_or(2)
+            && !required.contains(*piece)
+            && config.allows_token(piece)
+    ).map(
+        |(piece, count)| (piece, count * piece.len())
+    ).collect::<Vec<_>>();
+
+    // the order has to be deterministic
+    substrings.sort_by(|(p1, s1), (p2, s2)| s2.cmp(s1).then_with(|| p1.cmp(p2)));
+    substrings.truncate(config.dictionary_size * SEED_VOCABULARY_RATIO);
+
+    let mut scores = substrings.into_iter().map(
+        |(piece, score)| (piece.to_vec(), score as f64)
+    ).collect::<HashMap<_, _>>();
+
+    for piece in required.iter() {
+        scores.insert(piece.to_vec(), 1.0);
+    }
+
+    let total = scores.values().sum::<f64>();
+
+    scores.into_iter().map(
+        |(piece, score)| (piece, (score / total).ln())
+    ).collect()
+}
+
+// E step: forward-backward over the lattice of each word
+fn expected_counts(
+    words: &HashMap<Vec<u8>, usize>,
+    log_probs: &LogProbs,
+    max_len: usize,
+) -> HashMap<Vec<u8>, f64> {
+    let mut result = HashMap::with_capacity(log_probs.len());
+
+    for (word, count) in words.iter() {
+        let n = word.len();
+        let mut alpha = vec![f64::NEG_INFINITY; n + 1];
+        let mut beta = vec![f64::NEG_INFINITY; n + 1];
+        alpha[0] = 0.0;
+        beta[n] = 0.0;
+
+        for end in 1..=n {
+            for start in end.saturating_sub(max_len)..end {
+                if let Some(lp) = log_probs.get(&word[start..end]) {
+                    alpha[end] = log_sum_exp(alpha[end], alpha[start] + lp);
+                }
+            }
+        }
+
+        for start in (0..n).rev() {
+            for end in (start + 1)..=(start + max_len).min(n) {
+                if let Some(lp) = log_probs.get(&word[start..end]) {
+                    beta[start] = log_sum_exp(beta[start], lp + beta[end]);
+                }
+            }
+        }
+
+        let z = alpha[n];
+
+        if z == f64::NEG_INFINITY {
+            continue;
+        }
+
+        for start in 0..n {
+            for end in (start + 1)..=(start + max_len).min(n) {
+                if let Some(lp) = log_probs.get(&word[start..end]) {
+                    let p = (alpha[start] + lp + beta[end] - z).exp();
+                    *result.entry(word[start..end].to_vec()).or_insert(0.0) += p * *count as f64;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+// M step
+fn maximize(counts: &HashMap<Vec<u8>, f64>, required: &HashSet<Vec<u8>>) -> LogProbs {
+    let mut result = counts.iter().filter(
+        |(piece, count)| **count >= MINIMUM_EXPECTED_COUNT || required.contains(*piece)
+    ).map(
+        |(piece, count)| (piece.to_vec(), count.max(MINIMUM_EXPECTED_COUNT))
+    ).collect::<HashMap<_, _>>();
+
+    for piece in required.iter() {
+        if !result.contains_key(piece) {
+            result.insert(piece.to_vec(), MINIMUM_EXPECTED_COUNT);
+        }
+    }
+
+    let total = result.values().sum::<f64>();
+
+    for count in result.values_mut() {
+        *count = (*count / total).ln();
+    }
+
+    result
+}
+
+// It removes the pieces whose removal hurts the likelihood the least.
+fn prune(
+    words: &HashMap<Vec<u8>, usize>,
+    log_probs: &LogProbs,
+    required: &HashSet<Vec<u8>>,
+    target: usize,
+    max_len: usize,
+) -> LogProbs {
+    let mut frequencies = HashMap::with_capacity(log_probs.len());
+
+    for (word, count) in words.iter() {
+        for (start, end) in viterbi(word, log_probs, max_len, None, false) {
+            *frequencies.entry(&word[start..end]).or_insert(0) += *count;
+        }
+    }
+
+    let mut losses = vec![];
+
+    for (piece, lp) in log_probs.iter() {
+        if required.contains(piece) {
+            continue;
+        }
+
+        let frequency = frequencies.get(piece.as_slice()).copied().unwrap_or(0);
+
+        // if this piece is removed, it's segmented into the alternatives
+        let alternative = viterbi(piece, log_probs, max_len, None, true);
+
+        // nothing can replace it
+        if alternative.is_empty() {
+            losses.push((piece, f64::INFINITY));
+            continue;
+        }
+
+        let alternative = alternative.iter().map(
+            |(start, end)| log_probs.get(&piece[*start..*end]).unwrap()
+        ).sum::<f64>();
+
+        losses.push((piece, frequency as f64 * (lp - alternative)));
+    }
+
+    losses.sort_by(|(p1, l1), (p2, l2)| l2.total_cmp(l1).then_with(|| p1.cmp(p2)));
+    losses.truncate(target.saturating_sub(required.len()));
+
+    let mut result = losses.into_iter().map(
+        |(piece, _)| (piece.to_vec(), *log_probs.get(piece).unwrap())
+    ).collect::<HashMap<_, _>>();
+
+    for piece in required.iter() {
+        if let Some(lp) = log_probs.get(piece) {
+            result.insert(piece.to_vec(), *lp);
+        }
+    }
+
+    result
+}
+
+/// The most likely segmentation of `bytes`, as ranges.\
+/// If `unknown_log_prob` is set, a single byte that's not in `log_probs` has that log probability.
+/// Otherwise, an empty vector is returned if `bytes` cannot be segmented.
+/// If `skip_whole` is set, `bytes` itself is not used as a piece.
+pub fn viterbi(
+    bytes: &[u8],
+    log_probs: &LogProbs,
+    max_len: usize,
+    unknown_log_prob: Option<f64>,
+    skip_whole: bool,
+) -> Vec<(usize, usize)> {
+    let n = bytes.len();
+
+    // (best score, start of the last piece)
+    let mut best = vec![(f64::NEG_INFINITY, 0); n + 1];
+    best[0].0 = 0.0;
+
+    for end in 1..=n {
+        for start in end.saturating_sub(max_len)..end {
+            if best[start].0 == f64::NEG_INFINITY || (skip_whole && start == 0 && end == n) {
+                continue;
+            }
+
+            let lp = match log_probs.get(&bytes[start..end]) {
+                Some(lp) => *lp,
+                None if end - start == 1 && unknown_log_prob.is_some() => unknown_log_prob.unwrap(),
+                None => {
+                    continue;
+                },
+            };
+
+            if best[start].0 + lp > best[end].0 {
+                best[end] = (best[start].0 + lp, start);
+            }
+        }
+    }
+
+    if best[n].0 == f64::NEG_INFINITY {
+        return vec![];
+    }
+
+    let mut result = vec![];
+    let mut end = n;
+
+    while end > 0 {
+        let start = best[end].1;
+        result.push((start, end));
+        end = start;
+    }
+
+    result.reverse();
+    result
+}
+
+fn log_sum_exp(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        b
+    }
+
+    else if b == f64::NEG_INFINITY {
+        a
+    }
+
+    else if a > b {
+        a + (b - a).exp().ln_1p()
+    }
+
+    else {
+        b + (a - b).exp().ln_1p()
+    }
+}
@@ -0,0 +1,78 @@
+use super::*;
+use crate::{Model, construct_dictionary};
+use crate::test_utils::corpus;
+
+#[test]
+fn viterbi_test() {
+    let log_probs = vec![
+        (b"a".to_vec(), -2.0),
+        (b"b".to_vec(), -2.0),
+        (b"ab".to_vec(), -3.0),
+        (b"abc".to_vec(), -10.0),
+        (b"c".to_vec(), -2.0),
+    ].into_iter().collect::<HashMap<_, _>>();
+
+    assert_eq!(viterbi(b"abc", &log_probs, 4, None, false), vec![(0, 2), (2, 3)]);
+    assert_eq!(viterbi(b"ab", &log_probs, 4, None, true), vec![(0, 1), (1, 2)]);
+    assert_eq!(viterbi(b"abd", &log_probs, 4, None, false), vec![]);
+    assert_eq!(viterbi(b"abd", &log_probs, 4, Some(-20.0), false), vec![(0, 2), (2, 3)]);
+}
+
+#[test]
+fn unigram_test() {
+    let bytes = corpus(". 다람쥐 헌 쳇바퀴에 타고파. ".as_bytes());
+
+    let result = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_model(Model::Unigram)
+            .set_dictionary_size(400)
+            .to_owned(),
+    );
+
+    assert!(result.len() <= 400);
+    assert_eq!(result.len(), result.log_probs().len());
+
+    let mut sum = 0;
+
+    for (word, appearance) in result.iter() {
+        sum += word.len() * appearance;
+    }
+
+    assert_eq!(bytes.len(), sum);
+
+    let tokens = result.encode_viterbi(b"the lazy fox jumps over a quick dog. \xff");
+    assert_eq!(tokens.concat(), b"the lazy fox jumps over a quick dog. \xff");
+    assert!(tokens.len() < 20);
+}
+
+#[test]
+fn prune_test() {
+    let words = [("ab", 100), ("xy", 1)].into_iter().map(|(w, n)| (w.as_bytes().to_vec(), n)).collect();
+    let log_probs = [("a", -1.0), ("b", -1.0), ("ab", -1.0), ("xy", -5.0)].into_iter().map(
+        |(w, lp)| (w.as_bytes().to_vec(), lp)
+    ).collect::<LogProbs>();
+    let required = [b"a".to_vec(), b"b".to_vec()].into_iter().collect();
+
+    // `xy` cannot be segmented without itself, so it's kept even though it's rare
+    let pruned = prune(&words, &log_probs, &required, 3, 4);
+    assert!(pruned.contains_key(b"xy".as_slice()));
+    assert!(!pruned.contains_key(b"ab".as_slice()));
+}
+
+// characters count toward `dictionary_size`
+#[test]
+fn required_pieces_test() {
+    let bytes = (0..400).map(|i| char::from_u32(0xac00 + i).unwrap()).collect::<String>().repeat(3);
+
+    let result = construct_dictionary(
+        bytes.as_bytes(),
+        DictionaryConfig::default()
+            .set_model(Model::Unigram)
+            .set_dictionary_size(300)
+            .to_owned(),
+    );
+
+    assert!(result.len() <= 300);
+    assert_eq!(result.encode_viterbi(bytes.as_bytes()).concat(), bytes.as_bytes());
+}
@@ -15,3 +15,16 @@ pub fn prettify_file_size(bytes: u64) -> String {
         format!("{}GiB", bytes >> 30)
     }
 }
+
+/// in bytes, `None` if it's not available (it only works on Linux)
+pub fn resident_memory() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    for line in status.lines() {
+        if let Some(kb) = line.strip_prefix("VmRSS:") {
+            return kb.trim().trim_end_matches("kB").trim().parse::<usize>().ok().map(|kb| kb << 10);
+        }
+    }
+
+    None
+}
@@ -0,0 +1,340 @@
+use crate::bpe::{
+    Pair,
+    Unit,
+    UnitAllocator,
+    UnitMapInternal,
+    assign_pair_to_new_unit,
+    count_pairs,
+    from_pair,
+    pair_precedes,
+};
+use crate::dictionary::{Dictionary, DictionaryConfig, Model};
+use crate::log::write_log;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(test)]
+mod tests;
+
+/// Tokens in the middle of a word start with this.
+pub const CONTINUATION_PREFIX: &[u8] = b"##";
+
+/// A word that cannot be encoded becomes this token.
+pub const UNKNOWN_TOKEN: &[u8] = b"[UNK]";
+
+/// A word longer than this (in characters) becomes `UNKNOWN_TOKEN`.
+pub const MAXIMUM_WORD_LENGTH: usize = 100;
+
+/// BERT-style WordPiece.\
+/// `bytes` has to be normalized already. It's called by `construct_dictionary` when `config.model` is `Model::WordPiece`.
+pub(crate) fn construct_wordpiece_dictionary(bytes: &[u8], config: &DictionaryConfig) -> Dictionary {
+    let mut word_counts = HashMap::new();
+
+    for word in split_words(bytes, config.ultimate_separator) {
+        *word_counts.entry(word).or_insert(0) += 1;
+    }
+
+    // the order has to be deterministic
+    let mut word_counts = word_counts.into_iter().collect::<Vec<_>>();
+    word_counts.sort();
+
+    let mut unit_map = HashMap::new();
+    let mut units_by_bytes = HashMap::new();
+    let mut words = Vec::with_capacity(word_counts.len());
+
+    for (word, count) in word_counts.into_iter() {
+        let mut units = vec![];
+
+        for (index, symbol) in symbols(word).into_iter().enumerate() {
+            let symbol = if index == 0 {
+                symbol.to_vec()
+            } else {
+                [CONTINUATION_PREFIX, symbol].concat()
+            };
+
+            let unit = match units_by_bytes.get(&symbol) {
+                Some(unit) => *unit,
+                None => {
+                    let unit = unit_map.len() as Unit;
+                    unit_map.insert(unit, symbol.as_slice().into());
+                    units_by_bytes.insert(symbol, unit);
+                    unit
+                },
+            };
+
+            units.push(unit);
+        }
+
+        words.push((units, count));
+    }
+
+    let mut allocator = UnitAllocator::after(&unit_map);
+    let mut counts = Counts::new(&words);
+
+    // the result dictionary has `UNKNOWN_TOKEN`
+    while unit_map.len() + 1 < config.dictionary_size {
+        match best_pair(&counts, &unit_map, config) {
+            Some(pair) => {
+                let (c1, c2) = from_pair(pair);
+                let c2_bytes = unit_map.get(&c2).unwrap();
+                let new_bytes = [
+                    unit_map.get(&c1).unwrap().as_slice(),
+                    c2_bytes.strip_prefix(CONTINUATION_PREFIX).unwrap_or(c2_bytes),
+                ].concat();
+
+                // different merges can make the same bytes (e.g. `a` + `##bc` and `ab` + `##c`)
+                let new_unit = match units_by_bytes.get(&new_bytes) {
+                    Some(unit) => *unit,
+                    None => {
+                        let unit = allocator.allocate();
+                        unit_map.insert(unit, new_bytes.as_slice().into());
+                        units_by_bytes.insert(new_bytes, unit);
+                        unit
+                    },
+                };
+
+                let mut indices = counts.words_by_pair.remove(&pair).unwrap_or_default().into_iter().collect::<Vec<_>>();
+                indices.sort();
+
+                for index in indices.into_iter() {
+                    let (units, count) = &mut words[index];
+
+                    counts.remove(index, units, *count);
+                    *units = assign_pair_to_new_unit(units, pair, new_unit);
+                    counts.add(index, units, *count);
+                }
+            },
+            None => {
+                break;
+            },
+        }
+    }
+
+    write_log(
+        config.write_log_at.clone(),
+        "wordpiece",
+        &format!("constructed vocabulary with {} tokens", unit_map.len() + 1),
+    );
+
+    let mut appearances = unit_map.values().map(|bytes| (bytes.to_vec(), 0)).collect::<HashMap<_, _>>();
+    appearances.insert(UNKNOWN_TOKEN.to_vec(), 0);
+
+    for (units, count) in words.iter() {
+        for unit in units.iter() {
+            *appearances.get_mut(unit_map.get(unit).unwrap().as_slice()).unwrap() += *count;
+        }
+    }
+
+    Dictionary::from_words(appearances, Model::WordPiece)
+}
+
+// Counts of the units and the pairs of `words`, weighted by the counts of the words.
+// When a pair is merged, only the words that have the pair are counted again.
+struct Counts {
+    pairs: HashMap<Pair, usize>,
+    units: HashMap<Unit, usize>,
+
+    // indices of the words that have the pair
+    words_by_pair: HashMap<Pair, HashSet<usize>>,
+}
+
+impl Counts {
+    fn new(words: &[(Vec<Unit>, usize)]) -> Self {
+        let mut result = Counts {
+            pairs: HashMap::new(),
+            units: HashMap::new(),
+            words_by_pair: HashMap::new(),
+        };
+
+        for (index, (units, count)) in words.iter().enumerate() {
+            result.add(index, units, *count);
+        }
+
+        result
+    }
+
+    fn add(&mut self, index: usize, units: &[Unit], count: usize) {
+        for unit in units.iter() {
+            *self.units.entry(*unit).or_insert(0) += count;
+        }
+
+        for (pair, n) in count_pairs(units).into_iter() {
+            *self.pairs.entry(pair).or_insert(0) += n * count;
+            self.words_by_pair.entry(pair).or_default().insert(index);
+        }
+    }
+
+    fn remove(&mut self, index: usize, units: &[Unit], count: usize) {
+        for unit in units.iter() {
+            let n = self.units.get_mut(unit).unwrap();
+            *n -= count;
+
+            if *n == 0 {
+                self.units.remove(unit);
+            }
+        }
+
+        for (pair, n) in count_pairs(units).into_iter() {
+            let c = self.pairs.get_mut(&pair).unwrap();
+            *c -= n * count;
+
+            if *c == 0 {
+                self.pairs.remove(&pair);
+            }
+
+            if let Some(indices) = self.words_by_pair.get_mut(&pair) {
+                indices.remove(&index);
+
+                if indices.is_empty() {
+                    self.words_by_pair.remove(&pair);
+                }
+            }
+        }
+    }
+}
+
+// the pair with the best `count(ab) / (count(a) * count(b))`
+fn best_pair(
+    counts: &Counts,
+    unit_map: &UnitMapInternal,
+    config: &DictionaryConfig,
+) -> Option<Pair> {
+    let mut curr_best_pair = None;
+    let mut curr_best_score = 0.0;
+
+    for (pair, count) in counts.pairs.iter() {
+        if *count < config.minimum_appearance.unwrap_or(2) {
+            continue;
+        }
+
+        let (c1, c2) = from_pair(*pair);
+        let score = *count as f64 / (counts.units[&c1] as f64 * counts.units[&c2] as f64);
+
+        match curr_best_pair {
+            Some(best) if score < curr_best_score || (score == curr_best_score && !pair_precedes(*pair, best, unit_map)) => {
+                continue;
+            },
+            _ => {},
+        }
+
+        let c2_bytes = unit_map.get(&c2).unwrap();
+        let new_token = [
+            unit_map.get(&c1).unwrap().as_slice(),
+            c2_bytes.strip_prefix(CONTINUATION_PREFIX).unwrap_or(c2_bytes),
+        ].concat();
+
+        if !config.allows_token(new_token.strip_prefix(CONTINUATION_PREFIX).unwrap_or(&new_token)) {
+            continue;
+        }
+
+        curr_best_pair = Some(*pair);
+        curr_best_score = score;
+    }
+
+    curr_best_pair
+}
+
+/// BERT-style pre-tokenizer: it splits the input at whitespaces (which are dropped) and
+/// makes each ascii punctuation (and `ultimate_separator`) a word by itself.
+pub fn split_words(bytes: &[u8], ultimate_separator: Option<u8>) -> Vec<&[u8]> {
+    split_word_ranges(bytes, ultimate_separator).into_iter().map(
+        |(start, end)| &bytes[start..end]
+    ).collect()
+}
+
+/// `split_words`, with `(start, end)` of the words
+pub fn split_word_ranges(bytes: &[u8], ultimate_separator: Option<u8>) -> Vec<(usize, usize)> {
+    let mut result = vec![];
+    let mut start = 0;
+    let mut index = 0;
+
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let is_separator = c.is_ascii() && Some(c as u8) == ultimate_separator;
+
+            if c.is_whitespace() || c.is_ascii_punctuation() || is_separator {
+                if start < index {
+                    result.push((start, index));
+                }
+
+                if !c.is_whitespace() {
+                    result.push((index, index + 1));
+                }
+
+                start = index + c.len_utf8();
+            }
+
+            index += c.len_utf8();
+        }
+
+        index += chunk.invalid().len();
+    }
+
+    if start < bytes.len() {
+        result.push((start, bytes.len()));
+    }
+
+    result
+}
+
+// characters (or bytes of invalid UTF-8 sequences)
+fn symbols(word: &[u8]) -> Vec<&[u8]> {
+    let mut result = vec![];
+    let mut index = 0;
+
+    for chunk in word.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            result.push(&word[index..(index + c.len_utf8())]);
+            index += c.len_utf8();
+        }
+
+        for _ in chunk.invalid().iter() {
+            result.push(&word[index..(index + 1)]);
+            index += 1;
+        }
+    }
+
+    result
+}
+
+/// Greedy longest-match-first encoding of a single word.\
+/// It returns `UNKNOWN_TOKEN` if any part of the word cannot be matched.
+pub fn encode_word(word: &[u8], vocabulary: &HashMap<Vec<u8>, usize>) -> Vec<Vec<u8>> {
+    let symbols = symbols(word);
+
+    if symbols.len() > MAXIMUM_WORD_LENGTH {
+        return vec![UNKNOWN_TOKEN.to_vec()];
+    }
+
+    let mut result = vec![];
+    let mut start = 0;
+
+    while start < symbols.len() {
+        let mut matched = None;
+
+        for end in (start + 1..=symbols.len()).rev() {
+            let piece = symbols[start..end].concat();
+            let piece = if start == 0 {
+                piece
+            } else {
+                [CONTINUATION_PREFIX, &piece].concat()
+            };
+
+            if vocabulary.contains_key(&piece) {
+                matched = Some((piece, end));
+                break;
+            }
+        }
+
+        match matched {
+            Some((piece, end)) => {
+                result.push(piece);
+                start = end;
+            },
+            None => {
+                return vec![UNKNOWN_TOKEN.to_vec()];
+            },
+        }
+    }
+
+    result
+}
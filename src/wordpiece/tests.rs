@@ -0,0 +1,60 @@
+use super::*;
+use crate::{Model, construct_dictionary};
+use crate::test_utils::corpus;
+
+#[test]
+fn split_words_test() {
+    assert_eq!(
+        split_words(b"Hello, world!  it's\tme", None),
+        vec![&b"Hello"[..], b",", b"world", b"!", b"it", b"'", b"s", b"me"],
+    );
+}
+
+#[test]
+fn encode_word_test() {
+    let vocabulary = vec!["un", "##aff", "##able", "##a", "a"].into_iter().map(
+        |word| (word.as_bytes().to_vec(), 1)
+    ).collect::<HashMap<_, _>>();
+
+    assert_eq!(encode_word(b"unaffable", &vocabulary), vec![b"un".to_vec(), b"##aff".to_vec(), b"##able".to_vec()]);
+    assert_eq!(encode_word(b"unaffablex", &vocabulary), vec![UNKNOWN_TOKEN.to_vec()]);
+}
+
+#[test]
+fn wordpiece_test() {
+    let bytes = corpus(b", jumping foxes! ");
+
+    let result = construct_dictionary(
+        &bytes,
+        DictionaryConfig::default()
+            .set_model(Model::WordPiece)
+            .set_dictionary_size(80)
+            .to_owned(),
+    );
+
+    // tokens that different merges make are counted once
+    assert_eq!(result.len(), 80);
+    assert!(result.get(UNKNOWN_TOKEN).is_some());
+
+    let tokens = result.encode_wordpiece(b"the lazy foxes jumped, zzz");
+    let words = tokens.iter().filter(|token| !token.starts_with(CONTINUATION_PREFIX)).count();
+
+    // `the`, `lazy`, `foxes`, `jumped`, `,`, `zzz`
+    assert_eq!(words, 6);
+    assert_eq!(tokens.last().unwrap(), UNKNOWN_TOKEN);
+}
+
+#[test]
+fn model_mismatch_test() {
+    let result = construct_dictionary(
+        b"the quick brown fox jumps over the lazy dog",
+        DictionaryConfig::default()
+            .set_model(Model::WordPiece)
+            .set_dictionary_size(40)
+            .to_owned(),
+    );
+
+    assert_eq!(result.model(), Model::WordPiece);
+    assert!(std::panic::catch_unwind(|| result.encode_viterbi(b"the fox")).is_err());
+    assert!(std::panic::catch_unwind(|| result.encoder().encode(b"the fox")).is_err());
+}